@@ -0,0 +1,395 @@
+//! サーバーに組み込んで席を埋めるボット
+//!
+//! ボットは[`GameManager`]を直接受け取りますが、公平に対戦するため
+//! 盤面・得点・自分の手札・山札の枚数だけを見て手を選びます。
+
+use std::{
+    cmp::Reverse,
+    time::{Duration, Instant},
+};
+
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    game::{Board, GameManager, Kekka},
+    protocol::{Action, Attack, DashingAttack, Direction, Movement, PlayerID},
+};
+
+pub trait Bot: Send {
+    /// ログや棋譜に使う名前
+    fn name(&self) -> &str;
+    /// プレイヤー`id`として指す手を選ぶ。指せる手がなければ`None`
+    fn choose(&mut self, game_manager: &GameManager, id: PlayerID) -> Option<Action>;
+}
+
+/// 指せる手から一様に選ぶ
+pub struct RandomBot {
+    rng: ChaCha8Rng,
+}
+
+impl RandomBot {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Bot for RandomBot {
+    fn name(&self) -> &str {
+        "RandomBot"
+    }
+
+    fn choose(&mut self, game_manager: &GameManager, _id: PlayerID) -> Option<Action> {
+        game_manager.legal_actions().choose(&mut self.rng).copied()
+    }
+}
+
+/// 攻撃できれば一番多い枚数で攻撃し(上級ルールなら突進攻撃も)、できなければ次に攻撃できる距離へ詰める
+pub struct GreedyBot;
+
+impl Bot for GreedyBot {
+    fn name(&self) -> &str {
+        "GreedyBot"
+    }
+
+    fn choose(&mut self, game_manager: &GameManager, id: PlayerID) -> Option<Action> {
+        let actions = game_manager.legal_actions();
+        if let Some(attack) = strongest_attack(&actions) {
+            return Some(Action::Attack(attack));
+        }
+        if let Some(dash) = strongest_dashing_attack(&actions) {
+            return Some(Action::DashingAttack(dash));
+        }
+        let board = game_manager.board();
+        let hand = game_manager.player(id).hand();
+        movements(&actions)
+            .max_by_key(|&movement| {
+                let distance = distance_after(board, id, movement);
+                (
+                    movement.direction == Direction::Forward,
+                    count_after(hand, movement.card, distance) > 0,
+                    Reverse(distance),
+                )
+            })
+            .map(Action::Move)
+    }
+}
+
+/// 相手の攻撃が届かない距離を保ち、山札が尽きそうなら山札切れの判定で勝てる位置を選ぶ
+pub struct DefensiveBot;
+
+impl Bot for DefensiveBot {
+    fn name(&self) -> &str {
+        "DefensiveBot"
+    }
+
+    fn choose(&mut self, game_manager: &GameManager, id: PlayerID) -> Option<Action> {
+        let actions = game_manager.legal_actions();
+        if let Some(attack) = strongest_attack(&actions) {
+            return Some(Action::Attack(attack));
+        }
+        let board = game_manager.board();
+        let hand = game_manager.player(id).hand();
+        // 1枚引いて山札が尽きると、距離と同じカードの枚数、次に前に出ている方で勝敗が決まる
+        let last_draw = board.yamafuda().len() <= 1;
+        movements(&actions)
+            .max_by_key(|&movement| {
+                let distance = distance_after(board, id, movement);
+                let held = count_after(hand, movement.card, distance) as i32;
+                let advance = match movement.direction {
+                    Direction::Forward => movement.card as i32,
+                    Direction::Back => -(movement.card as i32),
+                };
+                if last_draw {
+                    held * 100 + advance
+                } else if distance > game_manager.rules().max_card() {
+                    1000 + held * 10 + advance
+                } else {
+                    // 届く距離しか選べなければ、なるべく離れる
+                    distance as i32 * 10 + held
+                }
+            })
+            .map(Action::Move)
+    }
+}
+
+/// 見えない札を毎回配り直して1つの木を共有するモンテカルロ木探索(ISMCTS)
+///
+/// 考える時間いっぱい(回数を決めたならその回数だけ)探索し、今のラウンドに勝つ見込みが最も高い手を選ぶ
+pub struct SearchBot {
+    rng: ChaCha8Rng,
+    think_time: Duration,
+    iterations: Option<u32>,
+}
+
+/// UCB1の探索の強さ
+const EXPLORATION: f64 = 0.7;
+
+/// 探索木のノード。ある手を指した直後の局面を表す
+struct Node {
+    action: Option<Action>,
+    /// この手を指したプレイヤー
+    player: PlayerID,
+    children: Vec<usize>,
+    visits: u32,
+    /// `player`から見た勝ち数。引き分けは0.5
+    wins: f64,
+    /// 親の局面でこの手が指せた回数。配り方によって指せない手があるので親の訪問数の代わりに使う
+    available: u32,
+}
+
+impl Node {
+    fn new(action: Option<Action>, player: PlayerID) -> Self {
+        Self {
+            action,
+            player,
+            children: Vec::new(),
+            visits: 0,
+            wins: 0.0,
+            available: 0,
+        }
+    }
+
+    fn ucb(&self) -> f64 {
+        let visits = self.visits as f64;
+        self.wins / visits + EXPLORATION * ((self.available as f64).ln() / visits).sqrt()
+    }
+}
+
+impl SearchBot {
+    pub fn new(seed: u64, think_time: Duration) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            think_time,
+            iterations: None,
+        }
+    }
+
+    /// 時間の代わりに探索の回数を決める。実行速度に関わらず、同じシード値なら同じ手を選ぶ
+    pub fn with_iterations(seed: u64, iterations: u32) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            think_time: Duration::ZERO,
+            iterations: Some(iterations),
+        }
+    }
+
+    /// 配り直した局面で木を1回たどり、プレイアウトの結果を記録する
+    fn iterate(&mut self, tree: &mut Vec<Node>, game_manager: &GameManager, id: PlayerID) {
+        let mut state = game_manager.determinize(id, &mut self.rng);
+        let mut path = vec![0];
+        let mut node = 0;
+        let winner = loop {
+            let actions = state.legal_actions();
+            let player = state.board().current_player();
+            if actions.is_empty() {
+                break Some(player.opposite());
+            }
+            let mut untried = Vec::new();
+            for &action in &actions {
+                match tree[node]
+                    .children
+                    .iter()
+                    .find(|&&child| tree[child].action == Some(action))
+                {
+                    Some(&child) => tree[child].available += 1,
+                    None => untried.push(action),
+                }
+            }
+            // 試していない手があれば木を1段伸ばしてプレイアウトに移る
+            if let Some(&action) = untried.choose(&mut self.rng) {
+                let child = tree.len();
+                tree.push(Node::new(Some(action), player));
+                tree[child].available = 1;
+                tree[node].children.push(child);
+                path.push(child);
+                break match step(&mut state, action) {
+                    Some(winner) => winner,
+                    None => playout(&mut state, &mut self.rng),
+                };
+            }
+            let child = tree[node]
+                .children
+                .iter()
+                .copied()
+                .filter(|&child| tree[child].action.is_some_and(|a| actions.contains(&a)))
+                .max_by(|&a, &b| tree[a].ucb().total_cmp(&tree[b].ucb()))
+                .expect("指せる手は全て試したはず");
+            path.push(child);
+            node = child;
+            if let Some(winner) = step(&mut state, tree[child].action.expect("根以外は手を持つ"))
+            {
+                break winner;
+            }
+        };
+        for index in path {
+            let node = &mut tree[index];
+            node.visits += 1;
+            node.wins += match winner {
+                Some(winner) if winner == node.player => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+        }
+    }
+}
+
+impl Bot for SearchBot {
+    fn name(&self) -> &str {
+        "SearchBot"
+    }
+
+    fn choose(&mut self, game_manager: &GameManager, id: PlayerID) -> Option<Action> {
+        let actions = game_manager.legal_actions();
+        if actions.len() <= 1 {
+            return actions.first().copied();
+        }
+        let deadline = Instant::now() + self.think_time;
+        let mut tree = vec![Node::new(None, id.opposite())];
+        for iteration in 1.. {
+            self.iterate(&mut tree, game_manager, id);
+            let done = match self.iterations {
+                Some(iterations) => iteration >= iterations,
+                None => Instant::now() >= deadline,
+            };
+            if done {
+                break;
+            }
+        }
+        tree[0]
+            .children
+            .iter()
+            .map(|&child| &tree[child])
+            .max_by_key(|node| node.visits)
+            .and_then(|node| node.action)
+    }
+}
+
+/// 手番のプレイヤーの手を進める。ラウンドが終われば勝者(引き分けは`None`)を返す
+fn step(game_manager: &mut GameManager, action: Action) -> Option<Option<PlayerID>> {
+    let id = game_manager.board().current_player();
+    match game_manager.play_action(id, action).expect("合法手のはず") {
+        Kekka::Continue => {
+            *game_manager.current_playerid_mut() = id.opposite();
+            None
+        }
+        Kekka::REnd(winner, _) => Some(winner),
+    }
+}
+
+/// ラウンドが終わるまで、攻撃できれば攻撃し、できなければランダムに動いて進める
+fn playout(game_manager: &mut GameManager, rng: &mut ChaCha8Rng) -> Option<PlayerID> {
+    loop {
+        let actions = game_manager.legal_actions();
+        let Some(&random) = actions.choose(rng) else {
+            return Some(game_manager.board().current_player().opposite());
+        };
+        let action = strongest_attack(&actions).map_or(random, Action::Attack);
+        if let Some(winner) = step(game_manager, action) {
+            return winner;
+        }
+    }
+}
+
+fn strongest_attack(actions: &[Action]) -> Option<Attack> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::Attack(attack) => Some(*attack),
+            _ => None,
+        })
+        .max_by_key(|attack| attack.quantity)
+}
+
+fn strongest_dashing_attack(actions: &[Action]) -> Option<DashingAttack> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::DashingAttack(dash) => Some(*dash),
+            _ => None,
+        })
+        .max_by_key(|dash| dash.quantity)
+}
+
+fn movements(actions: &[Action]) -> impl Iterator<Item = Movement> + '_ {
+    actions.iter().filter_map(|action| match action {
+        Action::Move(movement) => Some(*movement),
+        _ => None,
+    })
+}
+
+/// 移動したあとの相手との距離
+fn distance_after(board: &Board, id: PlayerID, movement: Movement) -> u8 {
+    let distance = board.pos(id).abs_diff(board.pos(id.opposite()));
+    match movement.direction {
+        Direction::Forward => distance - movement.card,
+        Direction::Back => distance + movement.card,
+    }
+}
+
+/// `card`を1枚使ったあとの手札に`distance`と同じカードが何枚残るか
+fn count_after(hand: &[u8], card: u8, distance: u8) -> usize {
+    let count = hand.iter().filter(|&&x| x == distance).count();
+    if card == distance {
+        count - 1
+    } else {
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::tests::setup, rules::RuleSet};
+
+    #[test]
+    fn greedy_attacks_with_as_many_cards_as_it_can() {
+        let game_manager = setup(
+            RuleSet::default(),
+            [10, 12],
+            &[2, 2, 2, 1, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        assert_eq!(
+            GreedyBot.choose(&game_manager, PlayerID::Zero),
+            Some(Action::Attack(Attack {
+                card: 2,
+                quantity: 3
+            }))
+        );
+    }
+
+    #[test]
+    fn defensive_keeps_out_of_reach_with_the_smallest_retreat() {
+        // 距離3で3を持たず、4か5で下がれば相手の一番大きいカードより遠くなる
+        let game_manager = setup(
+            RuleSet::default(),
+            [10, 13],
+            &[1, 2, 4, 5, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        assert_eq!(
+            DefensiveBot.choose(&game_manager, PlayerID::Zero),
+            Some(Action::Move(Movement {
+                card: 4,
+                direction: Direction::Back
+            }))
+        );
+    }
+
+    #[test]
+    fn search_with_a_fixed_budget_picks_the_same_legal_action() {
+        let game_manager = setup(
+            RuleSet::default(),
+            [10, 13],
+            &[1, 2, 4, 5, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        let choose = || SearchBot::with_iterations(7, 300).choose(&game_manager, PlayerID::Zero);
+        let action = choose().expect("指せる手はある");
+        assert!(game_manager.legal_actions().contains(&action));
+        assert_eq!(choose(), Some(action));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{fs, net::IpAddr, num::NonZeroUsize, path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use engarde_server::bot::{Bot, DefensiveBot, GreedyBot, RandomBot, SearchBot};
+use engarde_server::rules::{MatchFormat, RuleSet};
+use serde::Deserialize;
+
+const MAX_WIN: u32 = 100;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "EnGardeのサーバー",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 棋譜をGameManagerで再生し、記録どおりの結果になるか確かめる
+    Replay {
+        /// 棋譜ファイル(.jsonl)
+        file: PathBuf,
+        /// 盤面を表示せず検証だけ行う
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// レーティングのファイルを読み、順位表を表示する
+    Leaderboard {
+        /// `--ratings`で指定したファイル
+        file: PathBuf,
+        /// 上位何人まで表示するか
+        #[arg(short = 'n', long)]
+        top: Option<usize>,
+    },
+    /// 2つのボットを子プロセスとして起動し、標準入出力で1試合行う
+    Spawn {
+        /// p0のボットを起動するコマンド
+        bot0: String,
+        /// p1のボットを起動するコマンド
+        bot1: String,
+        /// 同じ山札で席を入れ替えてもう1試合行い、2試合の合計得点で勝敗を決める
+        #[arg(long)]
+        duplicate: bool,
+        #[command(flatten)]
+        match_args: MatchArgs,
+    },
+    /// 組み込みのボット同士を接続なしで大量に対戦させ、統計を表示する
+    Simulate(SimulateArgs),
+    /// 参加者の一覧から総当たりかスイス式で対戦させ、順位表を表示する
+    Tournament(TournamentArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// 待ち受けるアドレス
+    #[arg(short, long, default_value = "127.0.0.1")]
+    pub address: IpAddr,
+    /// 待ち受けるポート
+    #[arg(short, long, default_value_t = 12052)]
+    pub port: u16,
+    /// TCPの代わりにこのパスのUnixドメインソケットで待ち受ける
+    #[cfg(unix)]
+    #[arg(short, long)]
+    pub unix_socket: Option<PathBuf>,
+    /// p0の席を組み込みのボットで埋める
+    #[arg(long, value_enum)]
+    pub bot0: Option<BotKind>,
+    /// p1の席を組み込みのボットで埋める
+    #[arg(long, value_enum)]
+    pub bot1: Option<BotKind>,
+    /// 探索するボットが1手に考える時間(秒)
+    #[arg(long, default_value = "1", value_parser = parse_seconds)]
+    pub think_time: Duration,
+    #[command(flatten)]
+    pub match_args: MatchArgs,
+}
+
+/// 試合の終わり方。`--max-round`は`--first-to`・`--round-limit`と同時に指定するとエラーになる
+#[derive(clap::Args, Debug)]
+pub struct FormatArgs {
+    /// 両者の合計得点がこの値に達したら試合終了
+    #[arg(
+        short = 'r',
+        long,
+        default_value_t = MAX_WIN,
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["first_to", "round_limit"]
+    )]
+    pub max_round: u32,
+    /// どちらかがこのラウンド数に勝ったら試合終了
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub first_to: Option<u32>,
+    /// 引き分けを含めてこのラウンド数を終えたら試合終了。`--first-to`と両方指定すると早い方で終わる
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub round_limit: Option<u32>,
+}
+
+impl FormatArgs {
+    pub fn format(&self) -> MatchFormat {
+        if self.first_to.is_none() && self.round_limit.is_none() {
+            return MatchFormat::max_round(self.max_round);
+        }
+        MatchFormat {
+            max_round: None,
+            first_to: self.first_to,
+            round_limit: self.round_limit,
+        }
+    }
+}
+
+/// 試合の進め方。サーバーとして待ち受けるときも子プロセスと対戦するときも使う
+#[derive(clap::Args, Debug)]
+pub struct MatchArgs {
+    #[command(flatten)]
+    pub format: FormatArgs,
+    /// 山札シャッフルのシード値
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+    /// 試合結果を書き出すディレクトリ
+    #[arg(short, long)]
+    pub log_dir: Option<PathBuf>,
+    /// 1手あたりの制限時間(秒)。省略時は無制限
+    #[arg(short, long, value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+    /// 1試合を通した各プレイヤーの持ち時間(秒)。使い切ると負け
+    #[arg(short = 'b', long, value_parser = parse_seconds)]
+    pub time_bank: Option<Duration>,
+    /// 1手の制限時間を超えたときの扱い
+    #[arg(long, value_enum, default_value_t = TimeoutPenalty::Round)]
+    pub on_timeout: TimeoutPenalty,
+    /// 1手の間に続けて不正なメッセージを送ってよい回数
+    #[arg(
+        short = 'e',
+        long,
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub max_errors: u32,
+    /// 不正なメッセージが上限に達したときの罰則
+    #[arg(long, value_enum, default_value_t = ErrorPenalty::LoseRound)]
+    pub penalty: ErrorPenalty,
+    /// DoPlayの前に指せる手の一覧(LegalMoves)を送る
+    #[arg(long)]
+    pub legal_moves: bool,
+    /// ルールの設定ファイル(JSON)。省略時は標準ルール
+    #[arg(long, value_parser = load_rules)]
+    pub rules: Option<RuleSet>,
+    /// 試合が終わるたびにEloレーティングを更新するファイル(JSON)
+    #[arg(long)]
+    pub ratings: Option<PathBuf>,
+}
+
+impl MatchArgs {
+    pub fn rules(&self) -> RuleSet {
+        self.rules.clone().unwrap_or_default()
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SimulateArgs {
+    /// 偶数番目の試合でp0に座るボット。奇数番目の試合では席を入れ替える
+    #[arg(value_enum)]
+    pub bot0: BotKind,
+    /// 偶数番目の試合でp1に座るボット
+    #[arg(value_enum)]
+    pub bot1: BotKind,
+    /// 試合数
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub games: u64,
+    /// 並列に動かすスレッド数。省略時はCPUの数
+    #[arg(short = 'j', long)]
+    pub threads: Option<NonZeroUsize>,
+    #[command(flatten)]
+    pub format: FormatArgs,
+    /// 山札シャッフルのマスターシード値
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+    /// 探索するボットが1手に考える時間(秒)
+    #[arg(long, default_value = "1", value_parser = parse_seconds)]
+    pub think_time: Duration,
+    /// ルールの設定ファイル(JSON)。省略時は標準ルール
+    #[arg(long, value_parser = load_rules)]
+    pub rules: Option<RuleSet>,
+    /// 2試合ずつ同じ山札で席を入れ替えて行い、2試合の合計得点での勝率も表示する。奇数なら1試合増やす
+    #[arg(long)]
+    pub duplicate: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TournamentArgs {
+    /// 参加者の一覧(JSON)
+    pub roster: PathBuf,
+    /// 組み合わせ方
+    #[arg(short, long, value_enum, default_value_t = TournamentFormat::RoundRobin)]
+    pub format: TournamentFormat,
+    /// スイス式の回戦数。省略時は参加者数から決める
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub rounds: Option<u32>,
+    /// TCPのボットを待ち受けるアドレス
+    #[arg(short, long, default_value = "127.0.0.1")]
+    pub address: IpAddr,
+    /// TCPのボットを待ち受けるポート
+    #[arg(short, long, default_value_t = 12052)]
+    pub port: u16,
+    /// TCPのボットが試合に来るまで待つ時間(秒)。来なければその試合は負け
+    #[arg(long, default_value = "60", value_parser = parse_seconds)]
+    pub join_timeout: Duration,
+    /// 探索するボットが1手に考える時間(秒)
+    #[arg(long, default_value = "1", value_parser = parse_seconds)]
+    pub think_time: Duration,
+    /// 1組の2試合を同じ山札で行い、2試合の合計得点の多い方に勝点4(並べば2ずつ)を与える
+    #[arg(long)]
+    pub duplicate: bool,
+    #[command(flatten)]
+    pub match_args: MatchArgs,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TournamentFormat {
+    /// 総当たり
+    RoundRobin,
+    /// 勝点の近い相手と組むスイス式
+    Swiss,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPenalty {
+    /// そのラウンドを負けにする
+    Round,
+    /// その試合を負けにする
+    Game,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPenalty {
+    /// そのラウンドを負けにする
+    LoseRound,
+    /// ランダムな合法手を代わりに指す
+    RandomMove,
+    /// その試合を負けにする
+    Forfeit,
+}
+
+impl ErrorPenalty {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LoseRound => "LoseRound",
+            Self::RandomMove => "RandomMove",
+            Self::Forfeit => "Forfeit",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::LoseRound => "不正なメッセージが続いたためラウンド負け",
+            Self::RandomMove => "不正なメッセージが続いたためランダムな手を指します",
+            Self::Forfeit => "不正なメッセージが続いたため試合負け",
+        }
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BotKind {
+    /// 指せる手からランダムに選ぶ
+    Random,
+    /// 攻撃できれば攻撃し、攻撃できる距離へ詰める
+    Greedy,
+    /// 相手の攻撃が届かない距離を保ち、山札切れの判定を狙う
+    Defensive,
+    /// 見えない札を仮定してモンテカルロ木探索する
+    Search,
+}
+
+impl BotKind {
+    pub fn create(&self, seed: u64, think_time: Duration) -> Box<dyn Bot> {
+        match self {
+            Self::Random => Box::new(RandomBot::new(seed)),
+            Self::Greedy => Box::new(GreedyBot),
+            Self::Defensive => Box::new(DefensiveBot),
+            Self::Search => Box::new(SearchBot::new(seed, think_time)),
+        }
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs = s
+        .parse::<f64>()
+        .map_err(|_| format!("秒数として解釈できません: {}", s))?;
+    if secs.is_finite() && secs > 0.0 {
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err(format!("正の秒数を指定してください: {}", s))
+    }
+}
+
+fn load_rules(path: &str) -> Result<RuleSet, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    RuleSet::from_json(&json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn subcommands_do_not_clash() {
+        Cli::command().debug_assert();
+    }
+}
@@ -1,20 +1,122 @@
 use std::{
-    io::{self, BufRead, BufReader, BufWriter, Write},
-    net::TcpStream,
+    collections::HashSet,
+    fmt::Display,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
 };
 
 use serde::Serialize;
 
-use crate::protocol::PlayerID;
+use engarde_server::{bot::Bot, protocol::PlayerID};
+
+use crate::transport::Transport;
+
+#[derive(Debug)]
+pub enum MatchError {
+    Disconnected(PlayerID, io::Error),
+    TimedOut(PlayerID),
+    TooManyErrors(PlayerID),
+    Io(io::Error),
+}
+
+impl Display for MatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disconnected(id, e) => write!(f, "p{}が切断されました: {}", id.denote(), e),
+            Self::TimedOut(id) => write!(f, "p{}が制限時間を超えました", id.denote()),
+            Self::TooManyErrors(id) => {
+                write!(f, "p{}が不正なメッセージを繰り返しました", id.denote())
+            }
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for MatchError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// 接続中のクライアントが使っている名前の一覧
+#[derive(Clone, Default)]
+pub struct Names(Arc<Mutex<HashSet<String>>>);
+
+impl Names {
+    fn claim(&self, name: &str) -> bool {
+        self.0.lock().expect("lock失敗").insert(name.to_string())
+    }
+
+    fn release(&self, name: &str) {
+        self.0.lock().expect("lock失敗").remove(name);
+    }
+}
 
 pub struct Client {
-    reader: BufReader<TcpStream>,
-    writer: BufWriter<TcpStream>,
+    receiver: Receiver<io::Result<String>>,
+    writer: BufWriter<Box<dyn Write + Send>>,
+    closed: Option<io::Error>,
+    name: String,
+    names: Option<Names>,
+}
+
+fn read_lines<R: Read>(mut reader: BufReader<R>, sender: Sender<io::Result<String>>) {
+    loop {
+        let mut string = String::new();
+        let line = match reader.read_line(&mut string) {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "接続が閉じられました",
+            )),
+            Ok(_) => Ok(string.trim().to_string()),
+            Err(e) => Err(e),
+        };
+        let closed = line.is_err();
+        if sender.send(line).is_err() || closed {
+            break;
+        }
+    }
 }
 
 impl Client {
-    pub fn new(reader: BufReader<TcpStream>, writer: BufWriter<TcpStream>) -> Self {
-        Self { reader, writer }
+    pub fn new<T: Transport>(transport: T) -> io::Result<Self> {
+        let (reader, writer) = transport.split()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || read_lines(BufReader::new(reader), sender));
+        Ok(Self {
+            receiver,
+            writer: BufWriter::new(Box::new(writer)),
+            closed: None,
+            name: String::new(),
+            names: None,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 名前を登録する。他の接続中のクライアントが使っていれば`false`
+    pub fn register(&mut self, names: &Names, name: &str) -> bool {
+        if !names.claim(name) {
+            return false;
+        }
+        self.name = name.to_string();
+        self.names = Some(names.clone());
+        true
+    }
+
+    /// 名乗った名前の代わりに`name`を使う。登録していた名前は手放す
+    pub fn rename(&mut self, name: &str) {
+        if let Some(names) = self.names.take() {
+            names.release(&self.name);
+        }
+        self.name = name.to_string();
     }
 
     pub fn send<T>(&mut self, info: &T) -> io::Result<()>
@@ -27,39 +129,141 @@ impl Client {
         Ok(())
     }
 
-    pub fn read(&mut self) -> io::Result<String> {
-        let mut string = String::new();
-        self.reader.read_line(&mut string)?;
-        Ok(string.trim().to_string())
+    /// 1行読む。`deadline`を過ぎたら`TimedOut`を返す
+    pub fn read(&mut self, deadline: Option<Instant>) -> io::Result<String> {
+        if let Some(e) = self.closed.take() {
+            return Err(e);
+        }
+        let line = match deadline {
+            None => self.receiver.recv().ok(),
+            Some(deadline) => match self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => Some(line),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "制限時間を超えました",
+                    ))
+                }
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
+        line.unwrap_or_else(|| {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "接続が閉じられました",
+            ))
+        })
+    }
+
+    /// 切断されていないか。それまでに届いていた行は捨てる
+    pub fn is_connected(&mut self) -> bool {
+        self.discard_pending();
+        self.closed.is_none()
+    }
+
+    /// 手番外に届いていた行を捨てる。切断は捨てずに次の`read`で返す
+    pub fn discard_pending(&mut self) {
+        while let Ok(line) = self.receiver.try_recv() {
+            if let Err(e) = line {
+                self.closed = Some(e);
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(names) = &self.names {
+            names.release(&self.name);
+        }
+    }
+}
+
+/// 試合の席。接続してきたクライアントか、サーバーに組み込んだボット
+pub enum Seat {
+    Client(Client),
+    /// ログや棋譜に使う名前と、組み込みのボット
+    Bot(String, Box<dyn Bot>),
+}
+
+impl Seat {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Client(client) => client.name(),
+            Self::Bot(name, _) => name,
+        }
+    }
+
+    /// クライアントにメッセージを送る。ボットは盤面を直接見るので何もしない
+    pub fn send<T>(&mut self, info: &T) -> io::Result<()>
+    where
+        T: Serialize,
+    {
+        match self {
+            Self::Client(client) => client.send(info),
+            Self::Bot(..) => Ok(()),
+        }
     }
 }
 
 pub struct ClientManager {
-    client0: Client,
-    client1: Client,
+    seat0: Seat,
+    seat1: Seat,
 }
 
 impl ClientManager {
-    pub fn new(client0: Client, client1: Client) -> Self {
-        Self { client0, client1 }
+    pub fn new(seat0: Seat, seat1: Seat) -> Self {
+        Self { seat0, seat1 }
+    }
+
+    fn seat(&mut self, id: PlayerID) -> &mut Seat {
+        match id {
+            PlayerID::Zero => &mut self.seat0,
+            PlayerID::One => &mut self.seat1,
+        }
     }
 
-    pub fn client(&mut self, id: PlayerID) -> &mut Client {
+    pub fn name(&self, id: PlayerID) -> &str {
         match id {
-            PlayerID::Zero => &mut self.client0,
-            PlayerID::One => &mut self.client1,
+            PlayerID::Zero => self.seat0.name(),
+            PlayerID::One => self.seat1.name(),
+        }
+    }
+
+    /// その席がボットなら手を選ばせる
+    pub fn bot(&mut self, id: PlayerID) -> Option<&mut dyn Bot> {
+        match self.seat(id) {
+            Seat::Bot(_, bot) => Some(bot.as_mut()),
+            Seat::Client(_) => None,
         }
     }
 
-    pub fn send<T>(&mut self, id: PlayerID, info: &T) -> io::Result<()>
+    pub fn discard_pending(&mut self, id: PlayerID) {
+        if let Seat::Client(client) = self.seat(id) {
+            client.discard_pending();
+        }
+    }
+
+    pub fn send<T>(&mut self, id: PlayerID, info: &T) -> Result<(), MatchError>
     where
         T: Serialize,
     {
-        self.client(id).send(info)?;
-        Ok(())
+        self.seat(id)
+            .send(info)
+            .map_err(|e| MatchError::Disconnected(id, e))
     }
 
-    pub fn read(&mut self, id: PlayerID) -> io::Result<String> {
-        self.client(id).read()
+    pub fn read(&mut self, id: PlayerID, deadline: Option<Instant>) -> Result<String, MatchError> {
+        let Seat::Client(client) = self.seat(id) else {
+            unreachable!("ボットの席からは読まない");
+        };
+        client.read(deadline).map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut => MatchError::TimedOut(id),
+            _ => MatchError::Disconnected(id, e),
+        })
     }
 }
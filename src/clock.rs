@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+use engarde_server::protocol::PlayerID;
+
+use crate::cli::TimeoutPenalty;
+
+pub struct Clock {
+    move_time: Option<Duration>,
+    time_bank: Option<[Duration; 2]>,
+    penalty: TimeoutPenalty,
+    turn_start: Option<Instant>,
+}
+
+impl Clock {
+    pub fn new(
+        move_time: Option<Duration>,
+        time_bank: Option<Duration>,
+        penalty: TimeoutPenalty,
+    ) -> Self {
+        Self {
+            move_time,
+            time_bank: time_bank.map(|bank| [bank; 2]),
+            penalty,
+            turn_start: None,
+        }
+    }
+
+    pub fn penalty(&self) -> TimeoutPenalty {
+        self.penalty
+    }
+
+    pub fn time_left(&self, id: PlayerID) -> Option<Duration> {
+        self.time_bank.map(|banks| banks[id.denote() as usize])
+    }
+
+    pub fn out_of_time(&self, id: PlayerID) -> bool {
+        self.time_left(id).is_some_and(|left| left.is_zero())
+    }
+
+    /// 手番を開始する。再送中の場合は最初に開始した時刻を引き継ぐ
+    pub fn start_turn(&mut self) -> bool {
+        let fresh = self.turn_start.is_none();
+        self.turn_start.get_or_insert_with(Instant::now);
+        fresh
+    }
+
+    /// 今の手番に残された時間
+    pub fn turn_limit(&self, id: PlayerID) -> Option<Duration> {
+        let elapsed = self
+            .turn_start
+            .map(|start| start.elapsed())
+            .unwrap_or_default();
+        let move_left = self.move_time.map(|limit| limit.saturating_sub(elapsed));
+        let bank_left = self.time_left(id).map(|left| left.saturating_sub(elapsed));
+        match (move_left, bank_left) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn deadline(&self, id: PlayerID) -> Option<Instant> {
+        self.turn_limit(id).map(|limit| Instant::now() + limit)
+    }
+
+    /// 手番を終え、使った時間を持ち時間から引く
+    pub fn end_turn(&mut self, id: PlayerID) {
+        if let Some(start) = self.turn_start.take() {
+            if let Some(banks) = &mut self.time_bank {
+                let bank = &mut banks[id.denote() as usize];
+                *bank = bank.saturating_sub(start.elapsed());
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::protocol::ParseMessageError;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
+/// メッセージを解釈できなかった理由
 pub enum Errors {
     ParseMessage(ParseMessageError),
     Serde(serde_json::Error),
@@ -34,3 +36,98 @@ impl From<&'static str> for Errors {
         Self::Other(value)
     }
 }
+
+/// クライアントに返すエラー。`Error`メッセージの`Code`には変種名がそのまま入る
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameError {
+    /// そのカードを持っていない
+    NotInHand,
+    /// 移動先が相手の位置か、相手より向こう側
+    BlockedByOpponent,
+    /// 移動先が盤の外
+    OffBoard,
+    /// 相手との距離がカードの数字と違う
+    OutOfRange,
+    /// 攻撃に使う枚数だけ持っていない
+    NotEnoughCards,
+    /// 攻撃に使う枚数が0
+    NoCardsPlayed,
+    /// 手番でないプレイヤーが指そうとした
+    NotYourTurn,
+    /// メッセージを解釈できない
+    InvalidMessage,
+    /// `Evaluation`より先に`Play`が届いた
+    EvaluationRequired,
+    /// `Evaluation`が2回届いた
+    EvaluationAlreadyReceived,
+    /// 最初のメッセージが`PlayerName`ではない
+    NameRequired,
+    /// 名前が短すぎるか長すぎる
+    InvalidNameLength,
+    /// 名前に使えない文字がある
+    InvalidNameCharacter,
+    /// 名前が他のクライアントと同じ
+    NameTaken,
+    /// 突進攻撃を受け流したので後ろへ下がる移動しかできない
+    MustRetreat,
+    /// 上級ルールでない試合で突進攻撃を指そうとした
+    AdvancedRulesDisabled,
+    /// 大会の参加者でない名前で接続した
+    NotScheduled,
+}
+
+impl GameError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::NotInHand => "そのカードは持ってません!",
+            Self::BlockedByOpponent => "相手を越えては動けません!",
+            Self::OffBoard => "盤の外へは動けません!",
+            Self::OutOfRange => "攻撃はとどきません!",
+            Self::NotEnoughCards => "そんなに枚数持っていません!",
+            Self::NoCardsPlayed => "攻撃には1枚以上使ってください",
+            Self::NotYourTurn => "あなたの手番ではありません",
+            Self::InvalidMessage => "送信されたメッセージがおかしいです",
+            Self::EvaluationRequired => "先にEvalしてください",
+            Self::EvaluationAlreadyReceived => "もうEvalは受け取りました",
+            Self::NameRequired => "最初にPlayerNameを送ってください",
+            Self::InvalidNameLength => "名前は1文字以上32文字以下にしてください",
+            Self::InvalidNameCharacter => "名前に使えるのは半角英数字と-_.だけです",
+            Self::NameTaken => "その名前は既に使われています",
+            Self::MustRetreat => "突進攻撃を受け流したので後ろへ下がってください",
+            Self::AdvancedRulesDisabled => "上級ルールでないと突進攻撃はできません",
+            Self::NotScheduled => "その名前は大会に参加していません",
+        }
+    }
+}
+
+impl Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for GameError {}
+
+/// ルールの設定を使えない理由
+#[derive(Debug)]
+pub enum RuleError {
+    Serde(serde_json::Error),
+    Invalid(&'static str),
+}
+
+impl Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Serde(e) => write!(f, "ルールの設定を読めません: {}", e),
+            Self::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+impl From<serde_json::Error> for RuleError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Serde(value)
+    }
+}
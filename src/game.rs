@@ -1,20 +1,42 @@
-use crate::protocol::{Direction, PlayAttack, PlayMovement, PlayerID};
-use rand::prelude::SliceRandom;
+//! 盤面と手の適用
 
-const MOST_LEFT_SIDE: u8 = 1;
-const MOST_RIGHT_SIDE: u8 = 23;
+use std::fmt::Display;
+
+use crate::errors::GameError;
+use crate::protocol::{
+    Action, Attack, DashingAttack, Direction, Movement, PlayAttack, PlayDashingAttack,
+    PlayMovement, PlayerID,
+};
+use crate::rules::{MatchFormat, RuleSet, Tiebreak};
+use rand::{prelude::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// プレイヤー0の初期位置(盤の左端)
+pub const MOST_LEFT_SIDE: u8 = 1;
+/// 標準ルールでのプレイヤー1の初期位置(盤の右端)。ルールを変えると[`RuleSet::track_length`]になる
+pub const MOST_RIGHT_SIDE: u8 = 23;
+
+/// シード値と番号から別のシード値を作る(SplitMix64)
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
 
 struct Yamafuda;
 impl Yamafuda {
-    fn create() -> Vec<u8> {
-        let mut rng = rand::thread_rng();
-        let mut v = (1..=5).map(|i| [i; 5]).collect::<Vec<_>>().concat();
+    fn create(rules: &RuleSet, seed: u64) -> Vec<u8> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut v = rules.deck();
         v.shuffle(&mut rng);
         v
     }
 }
 
-#[derive(Debug)]
+/// 盤面。両者の位置と得点、山札、手番を持つ
+#[derive(Debug, Clone)]
 pub struct Board {
     p0_pos: u8,
     p1_pos: u8,
@@ -23,20 +45,23 @@ pub struct Board {
     //この中のu8はカード番号
     yamafuda: Vec<u8>,
     current_player: PlayerID,
+    most_right_side: u8,
 }
 
 impl Board {
-    fn new() -> Self {
+    fn new(yamafuda: Vec<u8>, most_right_side: u8) -> Self {
         Self {
             p0_pos: MOST_LEFT_SIDE,
-            p1_pos: MOST_RIGHT_SIDE,
+            p1_pos: most_right_side,
             p0_score: 0,
             p1_score: 0,
-            yamafuda: Yamafuda::create(),
+            yamafuda,
             current_player: PlayerID::Zero,
+            most_right_side,
         }
     }
 
+    /// 位置(1〜盤の右端)
     pub fn pos(&self, id: PlayerID) -> u8 {
         match id {
             PlayerID::Zero => self.p0_pos,
@@ -51,6 +76,7 @@ impl Board {
         }
     }
 
+    /// 山札。末尾から引く
     pub fn yamafuda(&self) -> &[u8] {
         &self.yamafuda
     }
@@ -59,6 +85,11 @@ impl Board {
         self.current_player
     }
 
+    /// 盤の右端。プレイヤー1の初期位置
+    pub fn most_right_side(&self) -> u8 {
+        self.most_right_side
+    }
+
     fn score_mut(&mut self, id: PlayerID) -> &mut u32 {
         match id {
             PlayerID::Zero => &mut self.p0_score,
@@ -67,18 +98,89 @@ impl Board {
     }
 }
 
+/// ラウンドが終わった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RoundEndReason {
+    /// 攻撃が当たった
+    Hit,
+    /// 相手が移動も攻撃もできなくなった
+    Tumi,
+    /// 山札が尽きた
+    DeckOut,
+    /// 1手の制限時間を超えた
+    Timeout,
+    /// 不正なメッセージの繰り返しによる罰則
+    Penalty,
+}
+
+impl RoundEndReason {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::Hit => "攻撃が当たりました",
+            Self::Tumi => "相手が動けなくなりました",
+            Self::DeckOut => "山札が尽きました",
+            Self::Timeout => "時間切れです",
+            Self::Penalty => "罰則です",
+        }
+    }
+}
+
+impl Display for RoundEndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+/// 手を指した結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Kekka {
-    REnd(Option<PlayerID>),
+    /// ラウンド終了。勝者がいなければ引き分け
+    REnd(Option<PlayerID>, RoundEndReason),
+    /// 相手の手番に移る
     Continue,
 }
 
-#[derive(Debug)]
+/// ルール以外の理由で試合を負けにする理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForfeitReason {
+    Disconnected,
+    MoveTimeout,
+    OutOfTime,
+    TooManyErrors,
+}
+
+impl ForfeitReason {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::Disconnected => "切断による不戦敗",
+            Self::MoveTimeout => "1手の制限時間超過による負け",
+            Self::OutOfTime => "持ち時間切れによる負け",
+            Self::TooManyErrors => "不正なメッセージの繰り返しによる負け",
+        }
+    }
+}
+
+impl Display for ForfeitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forfeit {
+    pub loser: PlayerID,
+    pub reason: ForfeitReason,
+}
+
+/// プレイヤーの手札
+#[derive(Debug, Clone)]
 pub struct Player {
     id: PlayerID,
     hand: Vec<u8>,
 }
 
 impl Player {
+    /// `card`の移動が盤の外にも相手を越えもしないか
     pub fn can_move(&self, board: &Board, card: u8, direction: Direction) -> bool {
         match (self.id, direction) {
             (PlayerID::Zero, Direction::Back) => {
@@ -87,18 +189,22 @@ impl Player {
             (PlayerID::Zero, Direction::Forward) => {
                 board.pos(self.id) + card < board.pos(self.id.opposite())
             }
-            (PlayerID::One, Direction::Back) => board.pos(self.id) + card <= MOST_RIGHT_SIDE,
+            (PlayerID::One, Direction::Back) => {
+                board.pos(self.id) + card <= board.most_right_side()
+            }
             (PlayerID::One, Direction::Forward) => {
                 board.pos(self.id).saturating_sub(card) > board.pos(self.id.opposite())
             }
         }
     }
+    /// 相手との距離が`card`と同じか
     pub fn can_attack(&self, board: &Board, card: u8) -> bool {
         match self.id {
             PlayerID::Zero => board.pos(self.id.opposite()) - board.pos(self.id) == card,
             PlayerID::One => board.pos(self.id) - board.pos(self.id.opposite()) == card,
         }
     }
+    /// 移動か攻撃のどれかができるか。できなければ詰み
     pub fn can_actions(&self, board: &Board) -> bool {
         let attacks = self.hand().iter().any(|&card| self.can_attack(board, card));
         let move_forwards = self
@@ -111,6 +217,26 @@ impl Player {
             .any(|&card| self.can_move(board, card, Direction::Back));
         attacks || move_forwards || move_backwards
     }
+    /// 指せる手の一覧。攻撃は1枚から持っている枚数までのそれぞれを含む
+    pub fn legal_actions(&self, board: &Board) -> Vec<Action> {
+        let mut cards = self.hand.clone();
+        cards.sort_unstable();
+        cards.dedup();
+        let mut actions = Vec::new();
+        for card in cards {
+            for direction in [Direction::Forward, Direction::Back] {
+                if self.can_move(board, card, direction) {
+                    actions.push(Action::Move(Movement { card, direction }));
+                }
+            }
+            if self.can_attack(board, card) {
+                for quantity in 1..=self.count_card(card) as u8 {
+                    actions.push(Action::Attack(Attack { card, quantity }));
+                }
+            }
+        }
+        actions
+    }
     pub fn card_pos(&self, card: u8) -> Option<usize> {
         self.hand.iter().position(|&x| x == card)
     }
@@ -136,20 +262,42 @@ impl Player {
     }
 }
 
+/// 1試合分の状態。手を適用し、ラウンドと試合の終了を判定する
+#[derive(Debug, Clone)]
 pub struct GameManager {
     p0: Player,
     p1: Player,
     board: Board,
     first_player: PlayerID,
-    game_end: Option<PlayerID>,
-    max_round: u32,
+    /// 試合が終わっていれば勝者。引き分けなら`Some(None)`
+    game_end: Option<Option<PlayerID>>,
+    forfeit: Option<Forfeit>,
+    format: MatchFormat,
+    seed: u64,
+    round: u32,
+    rules: RuleSet,
+    /// 突進攻撃を受け流し、次の手番で後ろへ下がらなければならないプレイヤー
+    retreating: Option<PlayerID>,
 }
 
 impl GameManager {
-    pub fn new(max_round: u32) -> Self {
-        let mut board = Board::new();
-        let p0_hand = board.yamafuda.split_off(board.yamafuda.len() - 5);
-        let p1_hand = board.yamafuda.split_off(board.yamafuda.len() - 5);
+    /// 両者の合計得点が`max_round`に達したら終わる試合を標準ルールで作る。山札は`seed`から決まる
+    pub fn new(max_round: u32, seed: u64) -> Self {
+        Self::with_rules(max_round, seed, RuleSet::default())
+    }
+    /// [`RuleSet`]を指定して試合を作る。`rules`は[`RuleSet::validate`]を通ったものにする
+    pub fn with_rules(max_round: u32, seed: u64, rules: RuleSet) -> Self {
+        Self::with_format(MatchFormat::max_round(max_round), seed, rules)
+    }
+    /// 試合の終わり方も指定して試合を作る。`format`は[`MatchFormat::validate`]を通ったものにする
+    pub fn with_format(format: MatchFormat, seed: u64, rules: RuleSet) -> Self {
+        let mut board = Board::new(
+            Yamafuda::create(&rules, derive_seed(seed, 0)),
+            rules.track_length,
+        );
+        let hand_size = rules.hand_size as usize;
+        let p0_hand = board.yamafuda.split_off(board.yamafuda.len() - hand_size);
+        let p1_hand = board.yamafuda.split_off(board.yamafuda.len() - hand_size);
         Self {
             p0: Player {
                 id: PlayerID::Zero,
@@ -162,9 +310,15 @@ impl GameManager {
             first_player: PlayerID::Zero,
             board,
             game_end: None,
-            max_round,
+            forfeit: None,
+            format,
+            seed,
+            round: 0,
+            rules,
+            retreating: None,
         }
     }
+    /// 次のラウンドの先手を入れ替える
     pub fn change_first_player(&mut self) {
         self.first_player = self.first_player.opposite();
         *self.current_playerid_mut() = self.first_player;
@@ -184,25 +338,135 @@ impl GameManager {
     pub fn board(&self) -> &Board {
         &self.board
     }
+    /// 手番のプレイヤーが指せる手の一覧。空なら詰み
+    pub fn legal_actions(&self) -> Vec<Action> {
+        self.actions_of(self.board.current_player)
+    }
+    /// 上級ルールの突進攻撃と、受け流したあとの後退を含めた`id`の指せる手
+    fn actions_of(&self, id: PlayerID) -> Vec<Action> {
+        let mut actions = self.player(id).legal_actions(&self.board);
+        if self.retreating == Some(id) {
+            actions.retain(|action| {
+                matches!(
+                    action,
+                    Action::Move(Movement {
+                        direction: Direction::Back,
+                        ..
+                    })
+                )
+            });
+        } else if self.rules.advanced {
+            actions.extend(self.dashing_attacks(id));
+        }
+        actions
+    }
+    fn dashing_attacks(&self, id: PlayerID) -> Vec<Action> {
+        let player = self.player(id);
+        let distance = self.board.pos(id).abs_diff(self.board.pos(id.opposite()));
+        let mut cards = player.hand.clone();
+        cards.sort_unstable();
+        cards.dedup();
+        let mut actions = Vec::new();
+        for move_card in cards {
+            if !player.can_move(&self.board, move_card, Direction::Forward) {
+                continue;
+            }
+            let card = distance - move_card;
+            for quantity in 1..=self.count_after_move(id, move_card, card) as u8 {
+                actions.push(Action::DashingAttack(DashingAttack {
+                    move_card,
+                    card,
+                    quantity,
+                }));
+            }
+        }
+        actions
+    }
+    /// `move_card`で移動したあとに`card`を何枚持っているか
+    fn count_after_move(&self, id: PlayerID, move_card: u8, card: u8) -> usize {
+        let count = self.player(id).count_card(card);
+        if card == move_card {
+            count.saturating_sub(1)
+        } else {
+            count
+        }
+    }
+    /// 突進攻撃を受け流して後ろへ下がらなければならないプレイヤー
+    pub fn retreating(&self) -> Option<PlayerID> {
+        self.retreating
+    }
+    /// `id`から見えない相手の手札と山札を混ぜて配り直した局面。探索で隠れた情報を仮定するのに使う
+    ///
+    /// 使われたカードは両者に見えているので、見えない札の組み合わせ自体は数えれば分かる
+    pub fn determinize<R: Rng>(&self, id: PlayerID, rng: &mut R) -> Self {
+        let mut game_manager = self.clone();
+        let opponent = id.opposite();
+        let mut hidden = game_manager.player(opponent).hand.clone();
+        hidden.extend_from_slice(&game_manager.board.yamafuda);
+        hidden.shuffle(rng);
+        let hand_len = game_manager.player(opponent).hand.len();
+        game_manager.player_mut(opponent).hand = hidden.split_off(hidden.len() - hand_len);
+        game_manager.board.yamafuda = hidden;
+        game_manager
+    }
     pub fn current_playerid_mut(&mut self) -> &mut PlayerID {
         &mut self.board.current_player
     }
-    pub fn ended(&self) -> Option<PlayerID> {
+    /// 試合が終わっていれば勝者。引き分けなら`Some(None)`
+    pub fn ended(&self) -> Option<Option<PlayerID>> {
         self.game_end
     }
-    pub fn kattahou(&self) -> PlayerID {
+    pub fn forfeited(&self) -> Option<Forfeit> {
+        self.forfeit
+    }
+    /// `loser`の負けで試合を終える
+    pub fn forfeit(&mut self, loser: PlayerID, reason: ForfeitReason) {
+        self.forfeit = Some(Forfeit { loser, reason });
+        self.game_end = Some(Some(loser.opposite()));
+    }
+    /// 得点の多い方。並んでいれば`None`
+    pub fn kattahou(&self) -> Option<PlayerID> {
         match self.board.p0_score.cmp(&self.board.p1_score) {
-            std::cmp::Ordering::Less => PlayerID::One,
-            std::cmp::Ordering::Greater => PlayerID::Zero,
-            std::cmp::Ordering::Equal => PlayerID::Zero,
+            std::cmp::Ordering::Less => Some(PlayerID::One),
+            std::cmp::Ordering::Greater => Some(PlayerID::Zero),
+            std::cmp::Ordering::Equal => None,
         }
     }
+    /// 試合の終わる条件を満たしていれば試合を終える。並んでいれば[`Tiebreak`]に従う
+    fn check_game_end(&mut self) {
+        let scores = [self.board.p0_score, self.board.p1_score];
+        if !self.format.reached(scores, self.round + 1) {
+            return;
+        }
+        match (self.kattahou(), self.rules.tiebreak) {
+            (Some(winner), _) => self.game_end = Some(Some(winner)),
+            (None, Tiebreak::Draw) => self.game_end = Some(None),
+            (None, Tiebreak::SuddenDeath) => {}
+        }
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+    pub fn format(&self) -> &MatchFormat {
+        &self.format
+    }
+    /// 今のラウンド番号(0始まり)
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+    /// 次のラウンドの山札を配り、位置を初期化する
     pub fn reset_round(&mut self) {
-        let mut yamafuda = Yamafuda::create();
-        self.p0.hand = yamafuda.split_off(yamafuda.len() - 5);
-        self.p1.hand = yamafuda.split_off(yamafuda.len() - 5);
+        self.round += 1;
+        let mut yamafuda = Yamafuda::create(&self.rules, derive_seed(self.seed, self.round as u64));
+        let hand_size = self.rules.hand_size as usize;
+        self.p0.hand = yamafuda.split_off(yamafuda.len() - hand_size);
+        self.p1.hand = yamafuda.split_off(yamafuda.len() - hand_size);
         self.board.p0_pos = MOST_LEFT_SIDE;
-        self.board.p1_pos = MOST_RIGHT_SIDE;
+        self.board.p1_pos = self.board.most_right_side;
+        self.retreating = None;
         self.board.yamafuda = yamafuda;
     }
     fn move_player(&mut self, id: PlayerID, direction: Direction, card: u8) {
@@ -220,60 +484,66 @@ impl GameManager {
         match have0.cmp(&have1) {
             std::cmp::Ordering::Less => {
                 self.board.p1_score += 1;
-                if self.board.p0_score + self.board.p1_score >= self.max_round {
-                    self.game_end = Some(self.kattahou());
-                }
-                Kekka::REnd(Some(PlayerID::One))
+                self.check_game_end();
+                Kekka::REnd(Some(PlayerID::One), RoundEndReason::DeckOut)
             }
             std::cmp::Ordering::Greater => {
                 self.board.p0_score += 1;
-                if self.board.p0_score + self.board.p1_score >= self.max_round {
-                    self.game_end = Some(self.kattahou());
-                }
-                Kekka::REnd(Some(PlayerID::Zero))
+                self.check_game_end();
+                Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::DeckOut)
             }
             std::cmp::Ordering::Equal => {
-                let distance_from_opposite_0 = MOST_RIGHT_SIDE - self.board.pos(PlayerID::Zero);
+                let distance_from_opposite_0 =
+                    self.board.most_right_side - self.board.pos(PlayerID::Zero);
                 let distance_from_opposite_1 = self.board.pos(PlayerID::One) - MOST_LEFT_SIDE;
                 match distance_from_opposite_0.cmp(&distance_from_opposite_1) {
                     std::cmp::Ordering::Less => {
                         self.board.p0_score += 1;
-                        if self.board.p0_score + self.board.p1_score >= self.max_round {
-                            self.game_end = Some(self.kattahou());
-                        }
-                        Kekka::REnd(Some(PlayerID::Zero))
+                        self.check_game_end();
+                        Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::DeckOut)
                     }
                     std::cmp::Ordering::Greater => {
                         self.board.p1_score += 1;
-                        if self.board.p0_score + self.board.p1_score >= self.max_round {
-                            self.game_end = Some(self.kattahou());
-                        }
-                        Kekka::REnd(Some(PlayerID::One))
+                        self.check_game_end();
+                        Kekka::REnd(Some(PlayerID::One), RoundEndReason::DeckOut)
+                    }
+                    std::cmp::Ordering::Equal => {
+                        self.check_game_end();
+                        Kekka::REnd(None, RoundEndReason::DeckOut)
                     }
-                    std::cmp::Ordering::Equal => Kekka::REnd(None),
                 }
             }
         }
     }
     fn round_end_attack(&mut self, id: PlayerID) -> Kekka {
         *self.board.score_mut(id) += 1;
-        if self.board.score(id) + self.board.score(id.opposite()) >= self.max_round {
-            self.game_end = Some(self.kattahou());
-        }
-        Kekka::REnd(Some(id))
+        self.check_game_end();
+        Kekka::REnd(Some(id), RoundEndReason::Hit)
+    }
+    /// `id`の負けでラウンドを終える
+    pub fn lose_round(&mut self, id: PlayerID, reason: RoundEndReason) -> Kekka {
+        let winner = id.opposite();
+        *self.board.score_mut(winner) += 1;
+        self.check_game_end();
+        Kekka::REnd(Some(winner), reason)
     }
     fn round_end_tumi(&mut self, id: PlayerID) -> Kekka {
         *self.board.score_mut(id) += 1;
-        if self.board.score(id) + self.board.score(id.opposite()) >= self.max_round {
-            self.game_end = Some(self.kattahou());
-        }
-        Kekka::REnd(Some(id))
+        self.check_game_end();
+        Kekka::REnd(Some(id), RoundEndReason::Tumi)
     }
+    /// 移動を適用する。指せない手なら理由を返し、状態は変わらない
     pub fn play_movement(
         &mut self,
         id: PlayerID,
         movement: &PlayMovement,
-    ) -> Result<Kekka, &'static str> {
+    ) -> Result<Kekka, GameError> {
+        if id != self.board.current_player {
+            return Err(GameError::NotYourTurn);
+        }
+        if self.retreating == Some(id) && movement.direction() == Direction::Forward {
+            return Err(GameError::MustRetreat);
+        }
         match self.player(id).card_pos(movement.play_card()) {
             Some(index) => {
                 if self
@@ -282,12 +552,13 @@ impl GameManager {
                 {
                     self.player_mut(id).remove_card(index);
                     self.move_player(id, movement.direction(), movement.play_card());
+                    self.retreating = None;
                     // 相手の詰み確認
-                    if !self.player(id.opposite()).can_actions(&self.board) {
+                    if self.actions_of(id.opposite()).is_empty() {
                         return Ok(self.round_end_tumi(id));
                     }
                     // 回収作業
-                    while self.player(id).hand().len() < 5 {
+                    while self.player(id).hand().len() < self.rules.hand_size as usize {
                         match self.board.yamafuda.pop() {
                             Some(card) => {
                                 self.player_mut(id).push_card(card);
@@ -300,57 +571,406 @@ impl GameManager {
                     }
                     Ok(Kekka::Continue)
                 } else {
-                    Err("そちらへは動けません!")
+                    match movement.direction() {
+                        Direction::Forward => Err(GameError::BlockedByOpponent),
+                        Direction::Back => Err(GameError::OffBoard),
+                    }
                 }
             }
-            None => Err("そのカードは持ってません!"),
+            None => Err(GameError::NotInHand),
         }
     }
-    pub fn play_attack(
+    /// [`Action`]を適用する
+    pub fn play_action(&mut self, id: PlayerID, action: Action) -> Result<Kekka, GameError> {
+        match action {
+            Action::Move(movement) => {
+                self.play_movement(id, &PlayMovement::new(movement.card, movement.direction))
+            }
+            Action::Attack(attack) => {
+                self.play_attack(id, &PlayAttack::new(attack.card, attack.quantity))
+            }
+            Action::DashingAttack(dash) => self.play_dashing_attack(
+                id,
+                &PlayDashingAttack::new(dash.move_card, dash.card, dash.quantity),
+            ),
+        }
+    }
+    /// 攻撃を適用する。指せない手なら理由を返し、状態は変わらない
+    pub fn play_attack(&mut self, id: PlayerID, attack: &PlayAttack) -> Result<Kekka, GameError> {
+        if id != self.board.current_player {
+            return Err(GameError::NotYourTurn);
+        }
+        if self.retreating == Some(id) {
+            return Err(GameError::MustRetreat);
+        }
+        let indicies = self.player(id).card_positions(attack.play_card());
+        if attack.num_of_card() == 0 {
+            Err(GameError::NoCardsPlayed)
+        } else if indicies.is_empty() {
+            Err(GameError::NotInHand)
+        } else if (indicies.len() as u8) < attack.num_of_card() {
+            Err(GameError::NotEnoughCards)
+        } else if !self.player(id).can_attack(&self.board, attack.play_card()) {
+            Err(GameError::OutOfRange)
+        } else {
+            Ok(self.strike(id, attack.play_card(), attack.num_of_card(), false))
+        }
+    }
+    /// 突進攻撃を適用する(上級ルール)。指せない手なら理由を返し、状態は変わらない
+    pub fn play_dashing_attack(
         &mut self,
         id: PlayerID,
-        attack: &PlayAttack,
-    ) -> Result<Kekka, &'static str> {
-        let indicies = self.player(id).card_positions(attack.play_card());
-        if indicies.len() as u8 >= attack.num_of_card()
-            && self.player(id).can_attack(&self.board, attack.play_card())
+        dash: &PlayDashingAttack,
+    ) -> Result<Kekka, GameError> {
+        if id != self.board.current_player {
+            return Err(GameError::NotYourTurn);
+        }
+        if !self.rules.advanced {
+            return Err(GameError::AdvancedRulesDisabled);
+        }
+        if self.retreating == Some(id) {
+            return Err(GameError::MustRetreat);
+        }
+        if dash.num_of_card() == 0 {
+            return Err(GameError::NoCardsPlayed);
+        }
+        let Some(index) = self.player(id).card_pos(dash.move_card()) else {
+            return Err(GameError::NotInHand);
+        };
+        if !self
+            .player(id)
+            .can_move(&self.board, dash.move_card(), Direction::Forward)
         {
-            let indicies_opposite = self
-                .player(id.opposite())
-                .card_positions(attack.play_card());
-            if indicies_opposite.len() >= indicies.len() {
-                indicies_opposite
-                    .into_iter()
-                    .rev()
-                    .take(indicies.len())
-                    .for_each(|index| self.player_mut(id.opposite()).remove_card(index));
+            return Err(GameError::BlockedByOpponent);
+        }
+        let count = self.count_after_move(id, dash.move_card(), dash.play_card());
+        let distance = self.board.pos(id).abs_diff(self.board.pos(id.opposite()));
+        if count == 0 {
+            Err(GameError::NotInHand)
+        } else if (count as u8) < dash.num_of_card() {
+            Err(GameError::NotEnoughCards)
+        } else if distance - dash.move_card() != dash.play_card() {
+            Err(GameError::OutOfRange)
+        } else {
+            self.player_mut(id).remove_card(index);
+            self.move_player(id, Direction::Forward, dash.move_card());
+            Ok(self.strike(id, dash.play_card(), dash.num_of_card(), true))
+        }
+    }
+    /// `card`を`quantity`枚使って攻撃する。相手が同じカードを`quantity`枚以上持っていれば
+    /// 両者が`quantity`枚ずつ出して受け流し、手札を補充して手番を終える
+    fn strike(&mut self, id: PlayerID, card: u8, quantity: u8, dashing: bool) -> Kekka {
+        let quantity = quantity as usize;
+        let indicies_opposite = self.player(id.opposite()).card_positions(card);
+        if indicies_opposite.len() >= quantity {
+            for player in [id, id.opposite()] {
+                let indicies = self.player(player).card_positions(card);
                 indicies
                     .into_iter()
                     .rev()
-                    .for_each(|index| self.player_mut(id).remove_card(index));
+                    .take(quantity)
+                    .for_each(|index| self.player_mut(player).remove_card(index));
+            }
+            if dashing {
+                self.retreating = Some(id.opposite());
+            }
 
-                // 相手の詰み確認
-                if !self.player(id.opposite()).can_actions(&self.board) {
-                    return Ok(Kekka::REnd(Some(id)));
-                }
-                // 回収作業
-                while self.player(id).hand().len() < 5 {
-                    match self.board.yamafuda.pop() {
-                        Some(card) => {
-                            self.player_mut(id).push_card(card);
-                        }
-                        None => return Ok(self.round_end_yamafuda()),
-                    }
-                    if self.board.yamafuda.is_empty() {
-                        return Ok(self.round_end_yamafuda());
+            // 相手の詰み確認
+            if self.actions_of(id.opposite()).is_empty() {
+                return self.round_end_tumi(id);
+            }
+            // 回収作業
+            while self.player(id).hand().len() < self.rules.hand_size as usize {
+                match self.board.yamafuda.pop() {
+                    Some(card) => {
+                        self.player_mut(id).push_card(card);
                     }
+                    None => return self.round_end_yamafuda(),
+                }
+                if self.board.yamafuda.is_empty() {
+                    return self.round_end_yamafuda();
                 }
-                Ok(Kekka::Continue)
-            } else {
-                Ok(self.round_end_attack(id))
             }
+            Kekka::Continue
         } else {
-            Err("攻撃はとどかないか、そんなに枚数持っていません！")
+            self.round_end_attack(id)
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 山札は残したまま、位置と手札を決めてp0の手番にした試合
+    pub(crate) fn setup(rules: RuleSet, pos: [u8; 2], hand0: &[u8], hand1: &[u8]) -> GameManager {
+        let mut game_manager = GameManager::with_rules(100, 0, rules);
+        game_manager.board.p0_pos = pos[0];
+        game_manager.board.p1_pos = pos[1];
+        game_manager.p0.hand = hand0.to_vec();
+        game_manager.p1.hand = hand1.to_vec();
+        game_manager
+    }
+
+    #[test]
+    fn attack_spends_only_the_requested_cards() {
+        let mut game_manager = setup(
+            RuleSet::default(),
+            [10, 11],
+            &[1, 1, 3, 4, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        let deck = game_manager.board().yamafuda().len();
+        let kekka = game_manager.play_attack(PlayerID::Zero, &PlayAttack::new(1, 1));
+        assert_eq!(kekka, Ok(Kekka::Continue));
+        assert_eq!(game_manager.player(PlayerID::One).hand(), &[2, 3, 4, 5]);
+        // 出した1枚だけ引き直す
+        assert_eq!(game_manager.board().yamafuda().len(), deck - 1);
+        assert_eq!(game_manager.player(PlayerID::Zero).hand().len(), 5);
+    }
+
+    #[test]
+    fn attack_with_more_cards_than_the_defender_holds_hits() {
+        let mut game_manager = setup(
+            RuleSet::default(),
+            [10, 11],
+            &[1, 1, 3, 4, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        let kekka = game_manager.play_attack(PlayerID::Zero, &PlayAttack::new(1, 2));
+        assert_eq!(
+            kekka,
+            Ok(Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::Hit))
+        );
+        assert_eq!(game_manager.board().score(PlayerID::Zero), 1);
+    }
+
+    #[test]
+    fn parry_spends_as_many_cards_as_the_attack() {
+        let mut game_manager = setup(
+            RuleSet::default(),
+            [10, 11],
+            &[1, 2, 3, 4, 5],
+            &[1, 1, 1, 4, 5],
+        );
+        let kekka = game_manager.play_attack(PlayerID::Zero, &PlayAttack::new(1, 1));
+        assert_eq!(kekka, Ok(Kekka::Continue));
+        assert_eq!(game_manager.player(PlayerID::One).count_card(1), 2);
+    }
+
+    #[test]
+    fn attack_with_as_many_cards_as_the_defender_holds_is_parried() {
+        let mut game_manager = setup(
+            RuleSet::default(),
+            [10, 11],
+            &[1, 1, 3, 4, 5],
+            &[1, 1, 3, 4, 5],
+        );
+        let deck = game_manager.board().yamafuda().len();
+        let kekka = game_manager.play_attack(PlayerID::Zero, &PlayAttack::new(1, 2));
+        assert_eq!(kekka, Ok(Kekka::Continue));
+        assert_eq!(game_manager.player(PlayerID::One).hand(), &[3, 4, 5]);
+        assert_eq!(game_manager.board().yamafuda().len(), deck - 2);
+        assert_eq!(game_manager.board().score(PlayerID::Zero), 0);
+    }
+
+    #[test]
+    fn attack_needs_at_least_one_card() {
+        let mut game_manager = setup(
+            RuleSet::default(),
+            [10, 11],
+            &[1, 1, 3, 4, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        let kekka = game_manager.play_attack(PlayerID::Zero, &PlayAttack::new(1, 0));
+        assert_eq!(kekka, Err(GameError::NoCardsPlayed));
+        assert_eq!(game_manager.player(PlayerID::Zero).hand(), &[1, 1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn every_listed_attack_is_a_different_move() {
+        let game_manager = setup(
+            RuleSet::default(),
+            [10, 11],
+            &[1, 1, 3, 4, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        let mut outcomes = Vec::new();
+        for action in game_manager.legal_actions() {
+            if let Action::Attack(_) = action {
+                let mut game_manager = game_manager.clone();
+                outcomes.push(game_manager.play_action(PlayerID::Zero, action));
+            }
+        }
+        assert_eq!(
+            outcomes,
+            [
+                Ok(Kekka::Continue),
+                Ok(Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::Hit))
+            ]
+        );
+    }
+
+    fn advanced() -> RuleSet {
+        RuleSet {
+            advanced: true,
+            ..RuleSet::default()
+        }
+    }
+
+    #[test]
+    fn parried_dashing_attack_forces_a_retreat() {
+        let mut game_manager = setup(advanced(), [5, 10], &[2, 3, 3, 4, 5], &[1, 3, 3, 4, 5]);
+        let dash = PlayDashingAttack::new(2, 3, 2);
+        let kekka = game_manager.play_dashing_attack(PlayerID::Zero, &dash);
+        assert_eq!(kekka, Ok(Kekka::Continue));
+        assert_eq!(game_manager.board().pos(PlayerID::Zero), 7);
+        assert_eq!(game_manager.player(PlayerID::One).hand(), &[1, 4, 5]);
+        assert_eq!(game_manager.retreating(), Some(PlayerID::One));
+
+        *game_manager.current_playerid_mut() = PlayerID::One;
+        assert!(game_manager.legal_actions().iter().all(|action| matches!(
+            action,
+            Action::Move(Movement {
+                direction: Direction::Back,
+                ..
+            })
+        )));
+        let forward = PlayMovement::new(1, Direction::Forward);
+        assert_eq!(
+            game_manager.play_movement(PlayerID::One, &forward),
+            Err(GameError::MustRetreat)
+        );
+        assert_eq!(
+            game_manager.play_attack(PlayerID::One, &PlayAttack::new(4, 1)),
+            Err(GameError::MustRetreat)
+        );
+        let back = PlayMovement::new(1, Direction::Back);
+        assert_eq!(
+            game_manager.play_movement(PlayerID::One, &back),
+            Ok(Kekka::Continue)
+        );
+        assert_eq!(game_manager.retreating(), None);
+    }
+
+    #[test]
+    fn dashing_attack_with_more_cards_than_the_defender_holds_hits() {
+        let mut game_manager = setup(advanced(), [5, 10], &[2, 3, 3, 4, 5], &[1, 3, 2, 4, 5]);
+        let dash = PlayDashingAttack::new(2, 3, 2);
+        let kekka = game_manager.play_dashing_attack(PlayerID::Zero, &dash);
+        assert_eq!(
+            kekka,
+            Ok(Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::Hit))
+        );
+    }
+
+    #[test]
+    fn dashing_attack_with_fewer_cards_than_the_defender_holds_is_parried() {
+        let mut game_manager = setup(advanced(), [5, 10], &[2, 3, 4, 4, 5], &[1, 3, 3, 4, 5]);
+        let dash = PlayDashingAttack::new(2, 3, 1);
+        let kekka = game_manager.play_dashing_attack(PlayerID::Zero, &dash);
+        assert_eq!(kekka, Ok(Kekka::Continue));
+        assert_eq!(game_manager.player(PlayerID::One).hand(), &[1, 3, 4, 5]);
+        assert_eq!(game_manager.retreating(), Some(PlayerID::One));
+    }
+
+    #[test]
+    fn defender_who_cannot_retreat_loses_the_round() {
+        let mut game_manager = setup(advanced(), [18, 23], &[2, 3, 3, 4, 5], &[1, 3, 4, 5, 5]);
+        let dash = PlayDashingAttack::new(2, 3, 1);
+        let kekka = game_manager.play_dashing_attack(PlayerID::Zero, &dash);
+        assert_eq!(
+            kekka,
+            Ok(Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::Tumi))
+        );
+        assert_eq!(game_manager.board().score(PlayerID::Zero), 1);
+    }
+
+    #[test]
+    fn parried_attacker_scores_when_the_defender_cannot_move() {
+        // p1は右端にいて下がれず、受け流しで1を使い切ると距離1で指せる手がない
+        let mut game_manager = setup(
+            RuleSet::default(),
+            [22, 23],
+            &[1, 2, 3, 4, 5],
+            &[1, 2, 3, 4, 5],
+        );
+        let kekka = game_manager.play_attack(PlayerID::Zero, &PlayAttack::new(1, 1));
+        assert_eq!(
+            kekka,
+            Ok(Kekka::REnd(Some(PlayerID::Zero), RoundEndReason::Tumi))
+        );
+        assert_eq!(game_manager.board().score(PlayerID::Zero), 1);
+        assert_eq!(game_manager.board().score(PlayerID::One), 0);
+    }
+
+    fn sudden_death(format: MatchFormat) -> GameManager {
+        let rules = RuleSet {
+            tiebreak: Tiebreak::SuddenDeath,
+            ..RuleSet::default()
+        };
+        GameManager::with_format(format, 0, rules)
+    }
+
+    #[test]
+    fn tie_at_max_round_is_a_draw_by_default() {
+        let mut game_manager = GameManager::new(2, 0);
+        game_manager.lose_round(PlayerID::One, RoundEndReason::Penalty);
+        game_manager.reset_round();
+        game_manager.lose_round(PlayerID::Zero, RoundEndReason::Penalty);
+        assert_eq!(game_manager.ended(), Some(None));
+    }
+
+    #[test]
+    fn sudden_death_plays_on_from_a_tie_at_max_round() {
+        let mut game_manager = sudden_death(MatchFormat::max_round(2));
+        game_manager.lose_round(PlayerID::One, RoundEndReason::Penalty);
+        game_manager.reset_round();
+        game_manager.lose_round(PlayerID::Zero, RoundEndReason::Penalty);
+        assert_eq!(game_manager.ended(), None);
+        game_manager.reset_round();
+        game_manager.lose_round(PlayerID::Zero, RoundEndReason::Penalty);
+        assert_eq!(game_manager.ended(), Some(Some(PlayerID::One)));
+    }
+
+    #[test]
+    fn sudden_death_plays_on_from_a_tie_at_the_round_limit() {
+        let mut game_manager = sudden_death(MatchFormat {
+            round_limit: Some(2),
+            ..MatchFormat::default()
+        });
+        game_manager.lose_round(PlayerID::One, RoundEndReason::Penalty);
+        game_manager.reset_round();
+        game_manager.lose_round(PlayerID::Zero, RoundEndReason::Penalty);
+        assert_eq!(game_manager.ended(), None);
+        game_manager.reset_round();
+        game_manager.lose_round(PlayerID::One, RoundEndReason::Penalty);
+        assert_eq!(game_manager.ended(), Some(Some(PlayerID::Zero)));
+    }
+
+    /// 両者が同じ距離にいて距離と同じカードを持たないまま山札が尽きた、引き分けのラウンド
+    fn draw_round(game_manager: &mut GameManager) -> Kekka {
+        game_manager.board.p0_pos = MOST_LEFT_SIDE;
+        game_manager.board.p1_pos = game_manager.board.most_right_side;
+        game_manager.p0.hand.clear();
+        game_manager.p1.hand.clear();
+        game_manager.round_end_yamafuda()
+    }
+
+    #[test]
+    fn drawn_rounds_count_toward_the_round_limit() {
+        let format = MatchFormat {
+            round_limit: Some(2),
+            ..MatchFormat::default()
+        };
+        let mut game_manager = GameManager::with_format(format, 0, RuleSet::default());
+        assert_eq!(
+            draw_round(&mut game_manager),
+            Kekka::REnd(None, RoundEndReason::DeckOut)
+        );
+        assert_eq!(game_manager.ended(), None);
+        game_manager.reset_round();
+        draw_round(&mut game_manager);
+        assert_eq!(game_manager.ended(), Some(None));
+    }
+}
@@ -0,0 +1,24 @@
+//! EnGardeのルールと通信メッセージ
+//!
+//! [`game::GameManager`]が1試合分の状態を持ち、手を適用して結果([`game::Kekka`])を返します。
+//! [`protocol`]にはサーバーとクライアントがやり取りするJSONメッセージの型があります。
+//! サーバー本体のほか、ボットや棋譜の解析ツールからも同じルールを使えます。
+//!
+//! ```
+//! use engarde_server::{game::GameManager, protocol::{Direction, PlayMovement, PlayerID}};
+//!
+//! let mut game_manager = GameManager::new(100, 0);
+//! let player = game_manager.player(PlayerID::Zero);
+//! let card = player.hand()[0];
+//! if player.can_move(game_manager.board(), card, Direction::Forward) {
+//!     game_manager
+//!         .play_movement(PlayerID::Zero, &PlayMovement::new(card, Direction::Forward))
+//!         .unwrap();
+//! }
+//! ```
+
+pub mod bot;
+pub mod errors;
+pub mod game;
+pub mod protocol;
+pub mod rules;
@@ -1,23 +1,42 @@
+mod cli;
 mod client_manager;
-mod errors;
-mod game;
-mod protocol;
+mod clock;
+mod rating;
+mod record;
+mod replay;
+mod simulate;
+mod tournament;
+mod transport;
 use std::{
-    env::args,
-    io::{self, stdout, BufReader, BufWriter, Write},
-    net::{SocketAddr, TcpListener},
+    io::{self, stdout, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    process::ExitCode,
+    sync::Arc,
     thread,
+    time::{Duration, Instant},
 };
 
-use game::{GameManager, Kekka};
-use protocol::{
-    BoardInfo, ConnectionStart, DoPlay, GameEnd, HandInfo, Messages, NameReceived, PlayedAttack,
-    PlayedMoveMent, PlayerID, RoundEnd, ServerError,
-};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
 
-use crate::client_manager::{Client, ClientManager};
+use clap::Parser;
+use cli::{Args, BotKind, Cli, Command, ErrorPenalty, MatchArgs, TimeoutPenalty};
+use clock::Clock;
+use engarde_server::bot::{Bot, RandomBot};
+use engarde_server::errors::GameError;
+use engarde_server::game::{derive_seed, ForfeitReason, GameManager, Kekka, RoundEndReason};
+use engarde_server::protocol::{
+    Action, BoardInfo, ConnectionStart, Disconnected, DoPlay, GameEnd, HandInfo, LegalMoves,
+    Messages, NameReceived, Penalty, PlayAttack, PlayDashingAttack, PlayMovement, PlayedAttack,
+    PlayedDashingAttack, PlayedMoveMent, PlayerID, PlayerName, RoundEnd, ServerError, Timeout,
+};
+use engarde_server::rules::{MatchFormat, RuleSet};
+use rand::Rng;
+use record::{Event, Record};
 
-const MAX_WIN: u32 = 100;
+use crate::client_manager::{Client, ClientManager, MatchError, Names, Seat};
+use crate::transport::{ChildProcess, Listener, Socket, Transport};
 
 fn print(string: &str) -> io::Result<()> {
     let mut stdout = stdout();
@@ -30,140 +49,294 @@ enum ProcessResult {
     Success(Kekka),
 }
 
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+fn board_info(game_manager: &GameManager, clock: &Clock) -> BoardInfo {
+    let mut info = BoardInfo::from_board(game_manager.board());
+    info.time_left_0 = clock.time_left(PlayerID::Zero).map(millis);
+    info.time_left_1 = clock.time_left(PlayerID::One).map(millis);
+    info
+}
+
+fn send_error(
+    client_manager: &mut ClientManager,
+    record: &mut Record,
+    id: PlayerID,
+    server_error: ServerError,
+) -> Result<(), MatchError> {
+    record.write(&Event::Error {
+        player: id,
+        code: Some(server_error.code),
+        message: server_error.message.clone(),
+    })?;
+    client_manager.send(id, &server_error)
+}
+
+/// 指せない手へのエラー。メッセージに指せる手の一覧を付ける
+fn illegal_error(game_manager: &GameManager, error: GameError) -> ServerError {
+    let actions = game_manager
+        .legal_actions()
+        .iter()
+        .map(|action| action.to_string())
+        .collect::<Vec<_>>();
+    let mut server_error = ServerError::new(error);
+    server_error.message = format!("{} 指せる手: {}", error, actions.join(" "));
+    server_error
+}
+
 fn process_turn(
     game_manager: &mut GameManager,
     client_manager: &mut ClientManager,
-) -> io::Result<ProcessResult> {
-    client_manager.send(
-        game_manager.board().current_player(),
-        &HandInfo::from_vec(
-            game_manager
-                .player(game_manager.board().current_player())
-                .hand(),
-        ),
-    )?;
-    client_manager.send(game_manager.board().current_player(), &DoPlay::new())?;
-
-    match Messages::parse(&client_manager.read(game_manager.board().current_player())?) {
-        Ok(message) => match message {
-            Messages::Eval(_) => {
-                match Messages::parse(&client_manager.read(game_manager.board().current_player())?)
-                {
-                    Err(e) => {
-                        print(format!("受信メッセージエラー: {}", e).as_str())?;
-                        client_manager.send(
-                            game_manager.board().current_player(),
-                            &ServerError::new("送信されたメッセージがおかしいです"),
-                        )?;
-                        Ok(ProcessResult::ReTry)
-                    }
-                    Ok(Messages::Eval(_)) => {
-                        client_manager.send(
-                            game_manager.board().current_player(),
-                            &ServerError::new("もうEvalは受け取りました"),
-                        )?;
-                        Ok(ProcessResult::ReTry)
-                    }
-                    Ok(Messages::PlayM(movement)) => {
-                        match game_manager
-                            .play_movement(game_manager.board().current_player(), &movement)
-                        {
-                            Ok(kekka @ Kekka::Continue) => {
-                                client_manager.send(
-                                    game_manager.board().current_player().opposite(),
-                                    &PlayedMoveMent::new(&movement),
-                                )?;
-                                Ok(ProcessResult::Success(kekka))
-                            }
-                            Ok(kekka @ Kekka::REnd(None)) => Ok(ProcessResult::Success(kekka)),
-                            Ok(kekka @ Kekka::REnd(Some(_))) => Ok(ProcessResult::Success(kekka)),
-                            Err(e) => {
-                                client_manager.send(
-                                    game_manager.board().current_player(),
-                                    &ServerError::new(e),
-                                )?;
-                                Ok(ProcessResult::ReTry)
-                            }
-                        }
-                    }
-                    Ok(Messages::PlayA(attack)) => {
-                        match game_manager
-                            .play_attack(game_manager.board().current_player(), &attack)
-                        {
-                            Ok(kekka @ Kekka::Continue) => {
-                                client_manager.send(
-                                    game_manager.board().current_player().opposite(),
-                                    &PlayedAttack::new(&attack),
-                                )?;
-                                Ok(ProcessResult::Success(kekka))
-                            }
-                            Ok(kekka @ Kekka::REnd(None)) => Ok(ProcessResult::Success(kekka)),
-                            Ok(kekka @ Kekka::REnd(Some(_))) => Ok(ProcessResult::Success(kekka)),
-                            Err(e) => {
-                                client_manager.send(
-                                    game_manager.board().current_player(),
-                                    &ServerError::new(e),
-                                )?;
-                                Ok(ProcessResult::ReTry)
-                            }
-                        }
+    clock: &Clock,
+    record: &mut Record,
+    legal_moves: bool,
+) -> Result<ProcessResult, MatchError> {
+    let id = game_manager.board().current_player();
+    client_manager.send(id, &HandInfo::from_vec(game_manager.player(id).hand()))?;
+    if legal_moves {
+        client_manager.send(id, &LegalMoves::new(game_manager.legal_actions()))?;
+    }
+    let mut do_play = DoPlay::new();
+    do_play.time_limit = clock.turn_limit(id).map(millis);
+    client_manager.send(id, &do_play)?;
+
+    let deadline = clock.deadline(id);
+    let error = match Messages::parse(&client_manager.read(id, deadline)?) {
+        Ok(Messages::Eval(_)) => match Messages::parse(&client_manager.read(id, deadline)?) {
+            Ok(message) => match message.action() {
+                Some(action) => {
+                    match play_action(
+                        game_manager,
+                        client_manager,
+                        record,
+                        action,
+                        &[id.opposite()],
+                    )? {
+                        Ok(kekka) => return Ok(ProcessResult::Success(kekka)),
+                        Err(e) => illegal_error(game_manager, e),
                     }
                 }
-            }
-            Messages::PlayM(_) => {
-                client_manager.send(
-                    game_manager.board().current_player(),
-                    &ServerError::new("先にEvalしてください"),
-                )?;
-                Ok(ProcessResult::ReTry)
-            }
-            Messages::PlayA(_) => {
-                client_manager.send(
-                    game_manager.board().current_player(),
-                    &ServerError::new("先にEvalしてください"),
-                )?;
-                Ok(ProcessResult::ReTry)
+                None => ServerError::new(GameError::EvaluationAlreadyReceived),
+            },
+            Err(e) => {
+                print(format!("受信メッセージエラー: {}", e).as_str())?;
+                ServerError::new(GameError::InvalidMessage)
             }
         },
+        Ok(Messages::PlayM(_) | Messages::PlayA(_) | Messages::PlayD(_)) => {
+            ServerError::new(GameError::EvaluationRequired)
+        }
         Err(e) => {
             print(format!("受信メッセージエラー: {}", e).as_str())?;
-            client_manager.send(
-                game_manager.board().current_player(),
-                &ServerError::new("送信されたメッセージがおかしいです"),
-            )?;
-            Ok(ProcessResult::ReTry)
+            ServerError::new(GameError::InvalidMessage)
+        }
+    };
+    send_error(client_manager, record, id, error)?;
+    Ok(ProcessResult::ReTry)
+}
+
+/// 手番のプレイヤーの手を指して棋譜に書き、`notify`の席に`Played`を送る。
+/// 指せない手なら状態を変えずに理由を返す
+fn play_action(
+    game_manager: &mut GameManager,
+    client_manager: &mut ClientManager,
+    record: &mut Record,
+    action: Action,
+    notify: &[PlayerID],
+) -> Result<Result<Kekka, GameError>, MatchError> {
+    let id = game_manager.board().current_player();
+    let kekka = match game_manager.play_action(id, action) {
+        Ok(kekka) => kekka,
+        Err(e) => return Ok(Err(e)),
+    };
+    match action {
+        Action::Move(movement) => {
+            record.write(&Event::Move {
+                player: id,
+                card: movement.card,
+                direction: movement.direction,
+                outcome: (&kekka).into(),
+            })?;
+            if let Kekka::Continue = kekka {
+                let movement = PlayMovement::new(movement.card, movement.direction);
+                for &to in notify {
+                    client_manager.send(to, &PlayedMoveMent::new(&movement))?;
+                }
+            }
+        }
+        Action::Attack(attack) => {
+            record.write(&Event::Attack {
+                player: id,
+                card: attack.card,
+                num_of_card: attack.quantity,
+                outcome: (&kekka).into(),
+            })?;
+            if let Kekka::Continue = kekka {
+                let attack = PlayAttack::new(attack.card, attack.quantity);
+                for &to in notify {
+                    client_manager.send(to, &PlayedAttack::new(&attack))?;
+                }
+            }
+        }
+        Action::DashingAttack(dash) => {
+            record.write(&Event::DashingAttack {
+                player: id,
+                move_card: dash.move_card,
+                card: dash.card,
+                num_of_card: dash.quantity,
+                outcome: (&kekka).into(),
+            })?;
+            if let Kekka::Continue = kekka {
+                let dash = PlayDashingAttack::new(dash.move_card, dash.card, dash.quantity);
+                for &to in notify {
+                    client_manager.send(to, &PlayedDashingAttack::new(&dash))?;
+                }
+            }
         }
     }
+    Ok(Ok(kekka))
+}
+
+/// サーバー側で選んだ手を指して両者に知らせる。指せる手がなければ詰みとしてラウンド負け
+fn play_chosen(
+    game_manager: &mut GameManager,
+    client_manager: &mut ClientManager,
+    record: &mut Record,
+    action: Option<Action>,
+) -> Result<ProcessResult, MatchError> {
+    let Some(action) = action else {
+        let id = game_manager.board().current_player();
+        return Ok(ProcessResult::Success(
+            game_manager.lose_round(id, RoundEndReason::Tumi),
+        ));
+    };
+    let kekka = play_action(
+        game_manager,
+        client_manager,
+        record,
+        action,
+        &[PlayerID::Zero, PlayerID::One],
+    )?
+    .expect("合法手のはず");
+    Ok(ProcessResult::Success(kekka))
+}
+
+fn apply_penalty(
+    game_manager: &mut GameManager,
+    client_manager: &mut ClientManager,
+    record: &mut Record,
+    penalty: ErrorPenalty,
+    penalty_bot: &mut RandomBot,
+) -> Result<ProcessResult, MatchError> {
+    let id = game_manager.board().current_player();
+    print(format!("p{}: {}\n", id.denote(), penalty.message()).as_str())?;
+    record.write(&Event::Penalty {
+        player: id,
+        policy: penalty.name().to_string(),
+    })?;
+    let notice = Penalty::new(id, penalty.name(), penalty.message());
+    client_manager.send(PlayerID::Zero, &notice)?;
+    client_manager.send(PlayerID::One, &notice)?;
+    match penalty {
+        ErrorPenalty::LoseRound => Ok(ProcessResult::Success(
+            game_manager.lose_round(id, RoundEndReason::Penalty),
+        )),
+        ErrorPenalty::RandomMove => {
+            let action = penalty_bot.choose(game_manager, id);
+            play_chosen(game_manager, client_manager, record, action)
+        }
+        ErrorPenalty::Forfeit => Err(MatchError::TooManyErrors(id)),
+    }
 }
 
 fn process_round(
     game_manager: &mut GameManager,
     client_manager: &mut ClientManager,
-) -> io::Result<()> {
+    clock: &mut Clock,
+    record: &mut Record,
+    args: &MatchArgs,
+    penalty_bot: &mut RandomBot,
+) -> Result<(), MatchError> {
+    record.write(&Event::round_start(game_manager))?;
+    let mut errors = 0;
     loop {
-        client_manager.send(PlayerID::Zero, &BoardInfo::from_board(game_manager.board()))?;
-        client_manager.send(PlayerID::One, &BoardInfo::from_board(game_manager.board()))?;
-        let result = process_turn(game_manager, client_manager)?;
+        client_manager.send(PlayerID::Zero, &board_info(game_manager, clock))?;
+        client_manager.send(PlayerID::One, &board_info(game_manager, clock))?;
+        let current = game_manager.board().current_player();
+        let bot_action = client_manager
+            .bot(current)
+            .map(|bot| bot.choose(game_manager, current));
+        let turn = match bot_action {
+            // 組み込みのボットは考える時間を`--think-time`で決めるので、時計を動かさない
+            Some(action) => play_chosen(game_manager, client_manager, record, action),
+            None => {
+                if clock.start_turn() {
+                    client_manager.discard_pending(current);
+                }
+                process_turn(
+                    game_manager,
+                    client_manager,
+                    clock,
+                    record,
+                    args.legal_moves,
+                )
+            }
+        };
+        let result = match turn {
+            Err(MatchError::TimedOut(id)) => {
+                record.write(&Event::Timeout { player: id })?;
+                clock.end_turn(id);
+                if clock.out_of_time(id) || clock.penalty() == TimeoutPenalty::Game {
+                    return Err(MatchError::TimedOut(id));
+                }
+                let timeout = Timeout::new(id, "制限時間超過によりラウンド負け");
+                client_manager.send(PlayerID::Zero, &timeout)?;
+                client_manager.send(PlayerID::One, &timeout)?;
+                ProcessResult::Success(game_manager.lose_round(id, RoundEndReason::Timeout))
+            }
+            result => result?,
+        };
+        let result = match result {
+            ProcessResult::ReTry => {
+                errors += 1;
+                if errors < args.max_errors {
+                    ProcessResult::ReTry
+                } else {
+                    apply_penalty(
+                        game_manager,
+                        client_manager,
+                        record,
+                        args.penalty,
+                        penalty_bot,
+                    )?
+                }
+            }
+            success => success,
+        };
+        if let ProcessResult::Success(_) = result {
+            errors = 0;
+            clock.end_turn(current);
+        }
         match result {
             ProcessResult::ReTry => {}
             ProcessResult::Success(Kekka::Continue) => {
                 *game_manager.current_playerid_mut() =
                     game_manager.board().current_player().opposite();
             }
-            ProcessResult::Success(Kekka::REnd(None)) => {
-                client_manager.send(PlayerID::Zero, &RoundEnd::hikiwake(game_manager.board()))?;
-                client_manager.send(PlayerID::One, &RoundEnd::hikiwake(game_manager.board()))?;
+            ProcessResult::Success(Kekka::REnd(None, reason)) => {
+                record.write(&Event::round_end(game_manager, None, reason))?;
+                let round_end = RoundEnd::hikiwake(game_manager.board(), reason);
+                client_manager.send(PlayerID::Zero, &round_end)?;
+                client_manager.send(PlayerID::One, &round_end)?;
                 break;
             }
-            ProcessResult::Success(Kekka::REnd(Some(winner))) => {
-                client_manager.send(
-                    PlayerID::Zero,
-                    &RoundEnd::win_lose(game_manager.board(), winner),
-                )?;
-                client_manager.send(
-                    PlayerID::One,
-                    &RoundEnd::win_lose(game_manager.board(), winner),
-                )?;
+            ProcessResult::Success(Kekka::REnd(Some(winner), reason)) => {
+                record.write(&Event::round_end(game_manager, Some(winner), reason))?;
+                let round_end = RoundEnd::win_lose(game_manager.board(), winner, reason);
+                client_manager.send(PlayerID::Zero, &round_end)?;
+                client_manager.send(PlayerID::One, &round_end)?;
                 break;
             }
         }
@@ -171,55 +344,733 @@ fn process_round(
     Ok(())
 }
 
-fn main() -> io::Result<()> {
-    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 12052)))?;
-    let (stream0, _) = listener.accept()?;
-    let mut client0 = Client::new(
-        BufReader::new(stream0.try_clone()?),
-        BufWriter::new(stream0),
-    );
-    let join0 = thread::spawn(move || -> io::Result<Client> {
-        client0.send(&ConnectionStart::new(PlayerID::Zero))?;
-        client0.read()?;
-        client0.send(&NameReceived::new())?;
-        Ok(client0)
-    });
-    let (stream1, _) = listener.accept()?;
-    let mut client1 = Client::new(
-        BufReader::new(stream1.try_clone()?),
-        BufWriter::new(stream1),
-    );
-    let join1 = thread::spawn(move || -> io::Result<Client> {
-        client1.send(&ConnectionStart::new(PlayerID::One))?;
-        client1.read()?;
-        client1.send(&NameReceived::new())?;
-        Ok(client1)
-    });
-    let client0 = join0.join().expect("join失敗")?;
-    let client1 = join1.join().expect("join失敗")?;
-    let mut client_manager = ClientManager::new(client0, client1);
-    let mut game_manager =
-        GameManager::new((|| args().nth(1)?.parse::<u32>().ok())().unwrap_or(MAX_WIN));
+fn try_accept<L: Listener>(listener: &L, args: &Args) -> io::Result<L::Stream> {
+    let stream = listener.accept_stream()?;
+    stream.set_write_timeout(args.match_args.timeout)?;
+    Ok(stream)
+}
+
+/// 接続の受付に続けて失敗したときに待つ時間。ファイル記述子の枯渇などで失敗し続けても空回りしないよう倍々に延ばす
+fn accept_backoff(failures: u32) -> Duration {
+    Duration::from_millis(10 << failures.min(7))
+}
+
+/// 2人目が来たときに1人目が切断していれば、2人目を1人目として待ち直す
+fn accept_pair<L: Listener>(listener: &L, args: &Args) -> (Client, Client) {
+    let mut first = accept_client(listener, args);
+    loop {
+        let second = accept_client(listener, args);
+        if first.is_connected() {
+            return (first, second);
+        }
+        let _ = print("対戦相手を待つ間に切断されたので、次の接続と組み直します\n");
+        first = second;
+    }
+}
+
+fn accept_client<L: Listener>(listener: &L, args: &Args) -> Client {
+    loop {
+        match Client::new(accept(listener, args)) {
+            Ok(client) => return client,
+            Err(e) => {
+                let _ = print(format!("接続受付エラー: {}\n", e).as_str());
+            }
+        }
+    }
+}
+
+fn accept<L: Listener>(listener: &L, args: &Args) -> L::Stream {
+    let mut failures = 0;
+    loop {
+        match try_accept(listener, args) {
+            Ok(stream) => return stream,
+            Err(e) => {
+                let _ = print(format!("接続受付エラー: {}\n", e).as_str());
+                thread::sleep(accept_backoff(failures));
+                failures += 1;
+            }
+        }
+    }
+}
+
+const MAX_NAME_LENGTH: usize = 32;
+
+fn validate_name(name: &str) -> Result<(), GameError> {
+    let length = name.chars().count();
+    if length == 0 || length > MAX_NAME_LENGTH {
+        return Err(GameError::InvalidNameLength);
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(GameError::InvalidNameCharacter);
+    }
+    Ok(())
+}
+
+fn handshake<T: Transport>(
+    transport: T,
+    id: PlayerID,
+    rules: RuleSet,
+    format: MatchFormat,
+    timeout: Option<Duration>,
+    max_errors: u32,
+    names: Names,
+) -> thread::JoinHandle<io::Result<Client>> {
+    thread::spawn(move || {
+        let client = Client::new(transport)?;
+        greet(client, id, rules, format, timeout, max_errors, names)
+    })
+}
+
+/// 接続したクライアントに席を知らせ、名前を受け取る
+fn greet(
+    mut client: Client,
+    id: PlayerID,
+    rules: RuleSet,
+    format: MatchFormat,
+    timeout: Option<Duration>,
+    max_errors: u32,
+    names: Names,
+) -> io::Result<Client> {
+    client.send(&ConnectionStart::new(id, rules, format))?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    for _ in 0..max_errors {
+        let line = client.read(deadline)?;
+        let error = match PlayerName::parse(&line) {
+            Err(e) => {
+                print(format!("受信メッセージエラー: {}\n", e).as_str())?;
+                GameError::NameRequired
+            }
+            Ok(player_name) => match validate_name(player_name.name()) {
+                Err(e) => e,
+                Ok(()) if !client.register(&names, player_name.name()) => GameError::NameTaken,
+                Ok(()) => {
+                    client.send(&NameReceived::new(client.name()))?;
+                    return Ok(client);
+                }
+            },
+        };
+        client.send(&ServerError::new(error))?;
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("p{}が正しいPlayerNameを送りませんでした", id.denote()),
+    ))
+}
+
+/// 試合に入る前の席
+enum Entrant<T> {
+    Remote(T),
+    /// 接続しただけで、まだ席を知らせていないクライアント
+    Connected(Client),
+    /// 名前の登録まで済んだクライアント
+    Joined(Client),
+    /// 組み込みのボットと、探索するボットが1手に考える時間、ログや棋譜に使う名前(`None`ならボットの名前)
+    Bot(BotKind, Duration, Option<String>),
+}
+
+impl<T: Transport> Entrant<T> {
+    fn join(
+        self,
+        id: PlayerID,
+        args: &MatchArgs,
+        names: Names,
+        seed: u64,
+    ) -> thread::JoinHandle<io::Result<Seat>> {
+        match self {
+            Self::Remote(transport) => {
+                let handshake = handshake(
+                    transport,
+                    id,
+                    args.rules(),
+                    args.format.format(),
+                    args.timeout,
+                    args.max_errors,
+                    names,
+                );
+                thread::spawn(move || handshake.join().expect("join失敗").map(Seat::Client))
+            }
+            Self::Connected(client) => {
+                let (rules, format) = (args.rules(), args.format.format());
+                let (timeout, max_errors) = (args.timeout, args.max_errors);
+                thread::spawn(move || {
+                    greet(client, id, rules, format, timeout, max_errors, names).map(Seat::Client)
+                })
+            }
+            Self::Joined(client) => thread::spawn(move || Ok(Seat::Client(client))),
+            Self::Bot(kind, think_time, name) => {
+                let bot = kind.create(seed, think_time);
+                let name = name.unwrap_or_else(|| bot.name().to_string());
+                thread::spawn(move || Ok(Seat::Bot(name, bot)))
+            }
+        }
+    }
+}
+
+fn process_game(
+    game_manager: &mut GameManager,
+    client_manager: &mut ClientManager,
+    clock: &mut Clock,
+    record: &mut Record,
+    args: &MatchArgs,
+) -> Result<(), MatchError> {
+    // ペナルティで指す手も試合のシード値から決まるように、ボットと重ならない番号から作る
+    let mut penalty_bot = RandomBot::new(derive_seed(game_manager.seed(), u64::MAX - 2));
     loop {
-        process_round(&mut game_manager, &mut client_manager)?;
+        process_round(
+            game_manager,
+            client_manager,
+            clock,
+            record,
+            args,
+            &mut penalty_bot,
+        )?;
         game_manager.reset_round();
         match game_manager.ended() {
             None => game_manager.change_first_player(),
-            Some(winner) => {
-                client_manager.send(PlayerID::Zero, &GameEnd::new(game_manager.board(), winner))?;
-                client_manager.send(PlayerID::One, &GameEnd::new(game_manager.board(), winner))?;
-                break;
-            }
+            Some(_) => return Ok(()),
+        }
+    }
+}
+
+fn update_ratings(
+    path: &Path,
+    client_manager: &ClientManager,
+    winner: Option<PlayerID>,
+    match_id: u64,
+) -> io::Result<()> {
+    let players = [
+        client_manager.name(PlayerID::Zero),
+        client_manager.name(PlayerID::One),
+    ];
+    // 同じボット同士の試合では強さが変わらないので数えない
+    if players[0] == players[1] {
+        return print(
+            format!(
+                "試合{}: 両者とも{}なのでレーティングは更新しません\n",
+                match_id, players[0]
+            )
+            .as_str(),
+        );
+    }
+    let message = match rating::update(path, players, winner) {
+        Ok([rating0, rating1]) => format!(
+            "試合{}: レーティング {}: {:.1}, {}: {:.1}\n",
+            match_id, players[0], rating0.rating, players[1], rating1.rating
+        ),
+        Err(e) => format!("試合{}: レーティングを保存できません: {}\n", match_id, e),
+    };
+    print(message.as_str())
+}
+
+/// 試合の結果
+struct MatchResult {
+    players: [String; 2],
+    /// 引き分けなら`None`
+    winner: Option<PlayerID>,
+    scores: [u32; 2],
+}
+
+/// 同じシード値で席を入れ替えた2試合の合計得点を、1試合目の席順で返す。多い方が勝ちで、並べば`None`
+///
+/// 同じ番号のラウンドは同じ山札から配られるので、両者が同じ手札と先手後手を1回ずつ受け持つ
+fn duplicate_result(first: [u32; 2], second: [u32; 2]) -> (Option<PlayerID>, [u32; 2]) {
+    let totals = [first[0] + second[1], first[1] + second[0]];
+    let winner = match totals[0].cmp(&totals[1]) {
+        std::cmp::Ordering::Greater => Some(PlayerID::Zero),
+        std::cmp::Ordering::Less => Some(PlayerID::One),
+        std::cmp::Ordering::Equal => None,
+    };
+    (winner, totals)
+}
+
+fn run_match<T0: Transport, T1: Transport>(
+    entrant0: Entrant<T0>,
+    entrant1: Entrant<T1>,
+    args: &MatchArgs,
+    names: [Names; 2],
+    match_seed: u64,
+    match_id: u64,
+) -> io::Result<MatchResult> {
+    let [names0, names1] = names;
+    // ボットの乱数はラウンドの山札と重ならない番号から作る
+    let join0 = entrant0.join(
+        PlayerID::Zero,
+        args,
+        names0,
+        derive_seed(match_seed, u64::MAX),
+    );
+    let join1 = entrant1.join(
+        PlayerID::One,
+        args,
+        names1,
+        derive_seed(match_seed, u64::MAX - 1),
+    );
+    let (seat0, seat1) = match (
+        join0.join().expect("join失敗"),
+        join1.join().expect("join失敗"),
+    ) {
+        (Ok(seat0), Ok(seat1)) => (seat0, seat1),
+        (Err(e), Ok(mut seat)) => {
+            let _ = seat.send(&Disconnected::new(PlayerID::Zero));
+            return Err(e);
+        }
+        (Ok(mut seat), Err(e)) => {
+            let _ = seat.send(&Disconnected::new(PlayerID::One));
+            return Err(e);
+        }
+        (Err(e), Err(_)) => return Err(e),
+    };
+    let mut client_manager = ClientManager::new(seat0, seat1);
+    let mut game_manager = GameManager::with_format(args.format.format(), match_seed, args.rules());
+    let mut clock = Clock::new(args.timeout, args.time_bank, args.on_timeout);
+    let mut record = Record::create(args.log_dir.as_deref(), match_id)?;
+    record.write(&Event::MatchStart {
+        match_id,
+        timestamp: record::timestamp(),
+        players: [
+            client_manager.name(PlayerID::Zero).to_string(),
+            client_manager.name(PlayerID::One).to_string(),
+        ],
+        seed: game_manager.seed(),
+        format: *game_manager.format(),
+        rules: game_manager.rules().clone(),
+    })?;
+    let result = process_game(
+        &mut game_manager,
+        &mut client_manager,
+        &mut clock,
+        &mut record,
+        args,
+    );
+    match result {
+        Ok(()) => {}
+        Err(e @ MatchError::Disconnected(id, _)) => {
+            print(format!("試合{}: {}\n", match_id, e).as_str())?;
+            game_manager.forfeit(id, ForfeitReason::Disconnected);
+            let _ = client_manager.send(id.opposite(), &Disconnected::new(id));
+        }
+        Err(e @ MatchError::TimedOut(id)) => {
+            print(format!("試合{}: {}\n", match_id, e).as_str())?;
+            let reason = if clock.out_of_time(id) {
+                ForfeitReason::OutOfTime
+            } else {
+                ForfeitReason::MoveTimeout
+            };
+            game_manager.forfeit(id, reason);
+            let timeout = Timeout::new(id, reason.message());
+            let _ = client_manager.send(PlayerID::Zero, &timeout);
+            let _ = client_manager.send(PlayerID::One, &timeout);
+        }
+        Err(e @ MatchError::TooManyErrors(id)) => {
+            print(format!("試合{}: {}\n", match_id, e).as_str())?;
+            game_manager.forfeit(id, ForfeitReason::TooManyErrors);
+        }
+        Err(MatchError::Io(e)) => return Err(e),
+    }
+    let winner = game_manager.ended().expect("試合が終わっていません");
+    let game_end = match (game_manager.forfeited(), winner) {
+        (Some(forfeit), _) => GameEnd::forfeit(game_manager.board(), forfeit),
+        (None, Some(winner)) => GameEnd::new(game_manager.board(), winner),
+        (None, None) => GameEnd::draw(game_manager.board()),
+    };
+    record.write(&Event::game_end(&game_manager, winner))?;
+    for id in [PlayerID::Zero, PlayerID::One] {
+        if game_manager.forfeited().is_some_and(|forfeit| {
+            forfeit.loser == id && forfeit.reason == ForfeitReason::Disconnected
+        }) {
+            continue;
+        }
+        if let Err(e) = client_manager.send(id, &game_end) {
+            print(format!("試合{}: {}\n", match_id, e).as_str())?;
         }
     }
-    print("ゲーム終了")?;
     print(
         format!(
-            "p0: {}点, p1: {}点",
+            "試合{}: ゲーム終了 (シード値: {}) {}: {}点, {}: {}点{}\n",
+            match_id,
+            game_manager.seed(),
+            client_manager.name(PlayerID::Zero),
             game_manager.board().score(PlayerID::Zero),
-            game_manager.board().score(PlayerID::One)
+            client_manager.name(PlayerID::One),
+            game_manager.board().score(PlayerID::One),
+            match (game_manager.forfeited(), winner) {
+                (Some(forfeit), _) => format!(" (p{}の{})", forfeit.loser.denote(), forfeit.reason),
+                (None, Some(_)) => String::new(),
+                (None, None) => " (引き分け)".to_string(),
+            }
+        )
+        .as_str(),
+    )?;
+    if let Some(path) = &args.ratings {
+        update_ratings(path, &client_manager, winner, match_id)?;
+    }
+    Ok(MatchResult {
+        players: [
+            client_manager.name(PlayerID::Zero).to_string(),
+            client_manager.name(PlayerID::One).to_string(),
+        ],
+        winner,
+        scores: [
+            game_manager.board().score(PlayerID::Zero),
+            game_manager.board().score(PlayerID::One),
+        ],
+    })
+}
+
+fn serve(args: Args) -> io::Result<()> {
+    // 両方の席がボットなら接続を待たずに1試合だけ行う
+    if let (Some(bot0), Some(bot1)) = (args.bot0, args.bot1) {
+        let master_seed = args
+            .match_args
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen());
+        print(
+            format!(
+                "ボット同士で対戦します ({}, シード値: {})\n",
+                args.match_args.format.format(),
+                master_seed
+            )
+            .as_str(),
+        )?;
+        let names = [Names::default(), Names::default()];
+        return run_match(
+            Entrant::<TcpStream>::Bot(bot0, args.think_time, None),
+            Entrant::<TcpStream>::Bot(bot1, args.think_time, None),
+            &args.match_args,
+            names,
+            derive_seed(master_seed, 0),
+            0,
+        )
+        .map(|_| ());
+    }
+    #[cfg(unix)]
+    if let Some(path) = &args.unix_socket {
+        let listener = UnixListener::bind(path)?;
+        return serve_on(listener, path.display().to_string(), args);
+    }
+    let listener = TcpListener::bind(SocketAddr::new(args.address, args.port))?;
+    let address = listener.local_addr()?.to_string();
+    serve_on(listener, address, args)
+}
+
+fn serve_on<L: Listener>(listener: L, address: String, args: Args) -> io::Result<()> {
+    let args = Arc::new(args);
+    let master_seed = args
+        .match_args
+        .seed
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    print(
+        format!(
+            "{}で待ち受け中 ({}, シード値: {})\n",
+            address,
+            args.match_args.format.format(),
+            master_seed
         )
         .as_str(),
     )?;
+    let names = Names::default();
+    for match_id in 0.. {
+        let (entrant0, entrant1) = match (args.bot0, args.bot1) {
+            (None, None) => {
+                let (first, second) = accept_pair(&listener, &args);
+                (Entrant::Connected(first), Entrant::Connected(second))
+            }
+            (bot0, bot1) => {
+                let entrant = |bot| match bot {
+                    Some(kind) => Entrant::Bot(kind, args.think_time, None),
+                    None => Entrant::Remote(accept(&listener, &args)),
+                };
+                (entrant(bot0), entrant(bot1))
+            }
+        };
+        let args = Arc::clone(&args);
+        let names = names.clone();
+        thread::spawn(move || {
+            if let Err(e) = run_match(
+                entrant0,
+                entrant1,
+                &args.match_args,
+                [names.clone(), names],
+                derive_seed(master_seed, match_id),
+                match_id,
+            ) {
+                let _ = print(format!("試合{}: エラー終了: {}\n", match_id, e).as_str());
+            }
+        });
+    }
     Ok(())
 }
+
+fn spawn(bot0: &str, bot1: &str, duplicate: bool, mut args: MatchArgs) -> io::Result<()> {
+    // 時間を超えたボットは強制終了するので、その試合は続けられない
+    args.on_timeout = TimeoutPenalty::Game;
+    let master_seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    print(
+        format!(
+            "ボットを起動します ({}, シード値: {})\n",
+            args.format.format(),
+            master_seed
+        )
+        .as_str(),
+    )?;
+    let match_seed = derive_seed(master_seed, 0);
+    let first = spawn_match(bot0, bot1, &args, match_seed, 0)?;
+    if !duplicate {
+        return Ok(());
+    }
+    // 2試合目は同じ山札で席を入れ替える
+    let second = spawn_match(bot1, bot0, &args, match_seed, 1)?;
+    let (winner, totals) = duplicate_result(first.scores, second.scores);
+    print(
+        format!(
+            "2試合の合計: {}: {}点, {}: {}点 {}\n",
+            first.players[0],
+            totals[0],
+            first.players[1],
+            totals[1],
+            match winner {
+                Some(id) => format!("{}の勝ち", first.players[id.denote() as usize]),
+                None => "引き分け".to_string(),
+            }
+        )
+        .as_str(),
+    )
+}
+
+fn spawn_match(
+    bot0: &str,
+    bot1: &str,
+    args: &MatchArgs,
+    match_seed: u64,
+    match_id: u64,
+) -> io::Result<MatchResult> {
+    let child0 = ChildProcess::spawn(bot0)?;
+    let child1 = ChildProcess::spawn(bot1)?;
+    // 同じボット同士でも対戦できるよう、名前の重複は確かめない
+    let names = [Names::default(), Names::default()];
+    run_match(
+        Entrant::Remote(child0),
+        Entrant::Remote(child1),
+        args,
+        names,
+        match_seed,
+        match_id,
+    )
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        None => serve(cli.args).map_err(|e| e.to_string()),
+        Some(Command::Replay { file, quiet }) => {
+            replay::replay(&file, quiet).map_err(|e| e.to_string())
+        }
+        Some(Command::Leaderboard { file, top }) => {
+            rating::leaderboard(&file, top).map_err(|e| e.to_string())
+        }
+        Some(Command::Spawn {
+            bot0,
+            bot1,
+            duplicate,
+            match_args,
+        }) => spawn(&bot0, &bot1, duplicate, match_args).map_err(|e| e.to_string()),
+        Some(Command::Tournament(args)) => tournament::tournament(args).map_err(|e| e.to_string()),
+        Some(Command::Simulate(args)) => {
+            simulate::simulate(&args);
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{pipe, BufRead, BufReader, PipeReader, PipeWriter};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[command(flatten)]
+        match_args: MatchArgs,
+    }
+
+    fn match_args(args: &[&str]) -> MatchArgs {
+        TestArgs::parse_from(["engarde_server"].iter().chain(args)).match_args
+    }
+
+    /// パイプの向こうのクライアントの振る舞い
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        /// `LegalMoves`の最初の手を指し続ける
+        Play,
+        /// 最初の`DoPlay`で切断する
+        HangUp,
+        /// `DoPlay`に答えない
+        Stall,
+    }
+
+    /// サーバーに渡す読み書きの組と、受け取ったメッセージの`Type`を返すクライアントのスレッド
+    fn piped_client(
+        name: &'static str,
+        behaviour: Behaviour,
+    ) -> ((PipeReader, PipeWriter), thread::JoinHandle<Vec<String>>) {
+        let (server_reader, mut writer) = pipe().unwrap();
+        let (reader, server_writer) = pipe().unwrap();
+        let client = thread::spawn(move || {
+            let mut send = |message: Value| writeln!(writer, "{}", message).is_ok();
+            let mut received = Vec::new();
+            let mut actions = Vec::new();
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else { break };
+                let message: Value = serde_json::from_str(&line).unwrap();
+                let typ = message["Type"].as_str().unwrap().to_string();
+                received.push(typ.clone());
+                match (typ.as_str(), behaviour) {
+                    ("ConnectionStart", _) => {
+                        send(
+                            serde_json::json!({"Type": "PlayerName", "From": "Client", "To": "Server", "Name": name}),
+                        );
+                    }
+                    ("LegalMoves", _) => actions = message["Actions"].as_array().unwrap().clone(),
+                    ("DoPlay", Behaviour::Play) => {
+                        let mut play = actions[0].clone();
+                        play["Type"] = "Play".into();
+                        play["From"] = "Client".into();
+                        play["To"] = "Server".into();
+                        send(
+                            serde_json::json!({"Type": "Evaluation", "From": "Client", "To": "Server"}),
+                        );
+                        send(play);
+                    }
+                    ("DoPlay", Behaviour::HangUp) => break,
+                    _ => {}
+                }
+            }
+            received
+        });
+        ((server_reader, server_writer), client)
+    }
+
+    fn play(behaviour: Behaviour, args: &[&str]) -> (MatchResult, Vec<String>) {
+        let (transport, client) = piped_client("piped", behaviour);
+        let result = run_match(
+            Entrant::Remote(transport),
+            Entrant::<ChildProcess>::Bot(BotKind::Greedy, Duration::from_millis(10), None),
+            &match_args(args),
+            [Names::default(), Names::default()],
+            1,
+            0,
+        )
+        .unwrap();
+        (result, client.join().unwrap())
+    }
+
+    #[test]
+    fn match_over_pipes_runs_to_the_end() {
+        let (result, received) = play(Behaviour::Play, &["-r", "1", "--legal-moves"]);
+        assert_eq!(
+            result.players,
+            ["piped".to_string(), "GreedyBot".to_string()]
+        );
+        assert_eq!(result.scores[0] + result.scores[1], 1);
+        assert_eq!(
+            received.first().map(String::as_str),
+            Some("ConnectionStart")
+        );
+        assert!(received.iter().any(|typ| typ == "NameReceived"));
+        assert_eq!(received.last().map(String::as_str), Some("GameEnd"));
+    }
+
+    #[test]
+    fn hanging_up_forfeits_the_match() {
+        let (result, received) = play(Behaviour::HangUp, &["--legal-moves"]);
+        assert_eq!(result.winner, Some(PlayerID::One));
+        assert_eq!(received.last().map(String::as_str), Some("DoPlay"));
+    }
+
+    #[test]
+    fn stalling_past_the_timeout_forfeits_the_match() {
+        let (result, received) = play(Behaviour::Stall, &["-t", "0.1", "--on-timeout", "game"]);
+        assert_eq!(result.winner, Some(PlayerID::One));
+        assert!(received.iter().any(|typ| typ == "Timeout"));
+        assert_eq!(received.last().map(String::as_str), Some("GameEnd"));
+    }
+
+    #[test]
+    fn recorded_match_replays_to_the_same_result() {
+        let log_dir = std::env::temp_dir().join(format!("engarde_record_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&log_dir);
+        let (result, _) = play(
+            Behaviour::Play,
+            &["-r", "5", "--legal-moves", "-l", log_dir.to_str().unwrap()],
+        );
+        let path = std::fs::read_dir(&log_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let record = std::fs::read_to_string(&path).unwrap();
+        let events = record
+            .lines()
+            .map(|line| serde_json::from_str::<Event>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Move { .. })));
+        let Some(Event::GameEnd { score0, score1, .. }) = events.last() else {
+            panic!("棋譜の最後がGameEndではありません");
+        };
+        assert_eq!([*score0, *score1], result.scores);
+        // 手と各手の結果、最終得点を再生して確かめる
+        assert!(replay::replay(&path, true).is_ok());
+        // 最終得点を書き換えると一致しない
+        let mut lines = record.lines().map(str::to_string).collect::<Vec<_>>();
+        let game_end = lines.pop().unwrap();
+        lines.push(game_end.replace(
+            &format!("\"Score0\":{}", score0),
+            &format!("\"Score0\":{}", score0 + 1),
+        ));
+        assert_ne!(lines.last(), Some(&game_end));
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        assert!(matches!(
+            replay::replay(&path, true),
+            Err(replay::ReplayError::Mismatch(..))
+        ));
+        std::fs::remove_dir_all(log_dir).unwrap();
+    }
+
+    #[derive(Parser)]
+    struct ServerArgs {
+        #[command(flatten)]
+        args: Args,
+    }
+
+    #[test]
+    fn client_who_leaves_while_waiting_is_not_paired() {
+        let args = ServerArgs::parse_from(["engarde_server"]).args;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let pairing = thread::spawn(move || accept_pair(&listener, &args));
+        drop(TcpStream::connect(address).unwrap());
+        // 切断が読み込みスレッドに届いてから次の2人が来る
+        thread::sleep(Duration::from_millis(100));
+        let _second = TcpStream::connect(address).unwrap();
+        let _third = TcpStream::connect(address).unwrap();
+        let (mut first, mut second) = pairing.join().unwrap();
+        assert!(first.is_connected());
+        assert!(second.is_connected());
+    }
+}
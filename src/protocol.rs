@@ -1,12 +1,19 @@
+//! サーバーとクライアントがやり取りするJSONメッセージ
+
 use std::{error::Error, fmt::Display};
 
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 use serde_aux::prelude::*;
 use serde_json::Value;
 use serde_with::skip_serializing_none;
 
-use crate::{errors::Errors, game::Board};
+use crate::{
+    errors::{Errors, GameError},
+    game::{Board, Forfeit, RoundEndReason},
+    rules::{MatchFormat, RuleSet},
+};
 
+/// プレイヤー番号。メッセージ中では`"0"`か`"1"`
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PlayerID {
     Zero,
@@ -91,7 +98,7 @@ where
     serializer.serialize_str(&num.to_string())
 }
 
-fn serialize_option_u8_as_string<S>(num: &Option<u8>, serializer: S) -> Result<S::Ok, S::Error>
+fn serialize_option_u64_as_string<S>(num: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -101,6 +108,13 @@ where
     }
 }
 
+fn serialize_direction_as_string<S>(direction: &Direction, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&direction.to_string())
+}
+
 fn serialize_u32_as_string<S>(num: &u32, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -108,6 +122,7 @@ where
     serializer.serialize_str(&num.to_string())
 }
 
+/// 盤面の通知
 #[derive(Serialize, Debug)]
 pub struct BoardInfo {
     #[serde(rename = "Type")]
@@ -148,6 +163,18 @@ pub struct BoardInfo {
     pub num_of_deck: u8,
     #[serde(rename = "CurrentPlayer", default)]
     pub current_player: PlayerID,
+    #[serde(
+        rename = "TimeLeft_0",
+        serialize_with = "serialize_option_u64_as_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_left_0: Option<u64>,
+    #[serde(
+        rename = "TimeLeft_1",
+        serialize_with = "serialize_option_u64_as_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_left_1: Option<u64>,
 }
 
 impl BoardInfo {
@@ -162,65 +189,40 @@ impl BoardInfo {
             player_score_1: board.score(PlayerID::One),
             num_of_deck: board.yamafuda().len() as u8,
             current_player: board.current_player(),
+            time_left_0: None,
+            time_left_1: None,
         }
     }
 }
 
-#[derive(Serialize)]
+/// 手札の通知。`Hand1`から手札の枚数だけキーが並ぶ
 pub struct HandInfo {
-    #[serde(rename = "Type")]
-    typ: &'static str,
-    #[serde(rename = "From")]
-    from: &'static str,
-    #[serde(rename = "To")]
-    to: &'static str,
-    #[serde(
-        rename = "Hand1",
-        deserialize_with = "deserialize_number_from_string",
-        serialize_with = "serialize_u8_as_string"
-    )]
-    pub hand1: u8,
-    #[serde(
-        rename = "Hand2",
-        deserialize_with = "deserialize_number_from_string",
-        serialize_with = "serialize_u8_as_string"
-    )]
-    pub hand2: u8,
-    #[serde(
-        rename = "Hand3",
-        deserialize_with = "deserialize_number_from_string",
-        serialize_with = "serialize_u8_as_string"
-    )]
-    pub hand3: u8,
-    #[serde(
-        rename = "Hand4",
-        serialize_with = "serialize_option_u8_as_string",
-        skip_serializing_if = "Option::is_none"
-    )]
-    pub hand4: Option<u8>,
-    #[serde(
-        rename = "Hand5",
-        serialize_with = "serialize_option_u8_as_string",
-        skip_serializing_if = "Option::is_none"
-    )]
-    pub hand5: Option<u8>,
+    pub hand: Vec<u8>,
 }
 
 impl HandInfo {
     pub fn from_vec(v: &[u8]) -> Self {
-        Self {
-            typ: "HandInfo",
-            from: "Server",
-            to: "Client",
-            hand1: v.first().copied().unwrap(),
-            hand2: v.get(1).copied().unwrap(),
-            hand3: v.get(2).copied().unwrap(),
-            hand4: v.get(3).copied(),
-            hand5: v.get(4).copied(),
+        Self { hand: v.to_vec() }
+    }
+}
+
+impl Serialize for HandInfo {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(3 + self.hand.len()))?;
+        map.serialize_entry("Type", "HandInfo")?;
+        map.serialize_entry("From", "Server")?;
+        map.serialize_entry("To", "Client")?;
+        for (index, card) in self.hand.iter().enumerate() {
+            map.serialize_entry(&format!("Hand{}", index + 1), &card.to_string())?;
         }
+        map.end()
     }
 }
 
+/// 手番の通知
 #[derive(Serialize)]
 pub struct DoPlay {
     #[serde(rename = "Type")]
@@ -233,6 +235,12 @@ pub struct DoPlay {
     pub message_id: u8,
     #[serde(rename = "Message")]
     message: &'static str,
+    #[serde(
+        rename = "TimeLimit",
+        serialize_with = "serialize_option_u64_as_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_limit: Option<u64>,
 }
 
 impl DoPlay {
@@ -243,10 +251,17 @@ impl DoPlay {
             to: "Client",
             message_id: 101,
             message: "a",
+            time_limit: None,
         }
     }
 }
 
+impl Default for DoPlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Serialize)]
 pub struct Accept {
     #[serde(rename = "Type")]
@@ -259,6 +274,7 @@ pub struct Accept {
     message_id: String,
 }
 
+/// 移動の向き。`Forward`は相手へ近づく
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Serialize)]
 pub enum Direction {
     Forward,
@@ -313,24 +329,84 @@ impl<'de> Deserialize<'de> for Direction {
     }
 }
 
+/// 移動の手
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Movement {
-    card: u8,
-    direction: Direction,
+    #[serde(
+        rename = "PlayCard",
+        serialize_with = "serialize_u8_as_string",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub card: u8,
+    #[serde(rename = "Direction", serialize_with = "serialize_direction_as_string")]
+    pub direction: Direction,
 }
 
+/// 攻撃の手。`quantity`枚の`card`で攻撃する
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Attack {
-    card: u8,
-    quantity: u8,
+    #[serde(
+        rename = "PlayCard",
+        serialize_with = "serialize_u8_as_string",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub card: u8,
+    #[serde(
+        rename = "NumOfCard",
+        serialize_with = "serialize_u8_as_string",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub quantity: u8,
 }
 
+/// 突進攻撃の手(上級ルール)。`move_card`で前に出てから、`quantity`枚の`card`で攻撃する
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct DashingAttack {
+    #[serde(
+        rename = "MoveCard",
+        serialize_with = "serialize_u8_as_string",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub move_card: u8,
+    #[serde(
+        rename = "PlayCard",
+        serialize_with = "serialize_u8_as_string",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub card: u8,
+    #[serde(
+        rename = "NumOfCard",
+        serialize_with = "serialize_u8_as_string",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub quantity: u8,
+}
+
+/// 指せる手の1つ。`MessageID`で移動(101)か攻撃(102)か突進攻撃(103)かを表す
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "MessageID")]
 pub enum Action {
+    #[serde(rename = "101")]
     Move(Movement),
+    #[serde(rename = "102")]
     Attack(Attack),
+    #[serde(rename = "103")]
+    DashingAttack(DashingAttack),
 }
 
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Move(movement) => write!(f, "{}{}", movement.card, movement.direction),
+            Self::Attack(attack) => write!(f, "{}x{}", attack.card, attack.quantity),
+            Self::DashingAttack(dash) => {
+                write!(f, "{}F{}x{}", dash.move_card, dash.card, dash.quantity)
+            }
+        }
+    }
+}
+
+/// 相手が指した移動の通知
 #[derive(Serialize)]
 pub struct PlayedMoveMent {
     #[serde(rename = "Type")]
@@ -360,6 +436,7 @@ impl PlayedMoveMent {
     }
 }
 
+/// 相手が指した攻撃の通知
 #[derive(Serialize)]
 pub struct PlayedAttack {
     #[serde(rename = "Type")]
@@ -389,6 +466,40 @@ impl PlayedAttack {
     }
 }
 
+/// 相手が指した突進攻撃の通知
+#[derive(Serialize)]
+pub struct PlayedDashingAttack {
+    #[serde(rename = "Type")]
+    typ: &'static str,
+    #[serde(rename = "From")]
+    from: &'static str,
+    #[serde(rename = "To")]
+    to: &'static str,
+    #[serde(rename = "MessageID")]
+    pub message_id: &'static str,
+    #[serde(rename = "MoveCard", serialize_with = "serialize_u8_as_string")]
+    pub move_card: u8,
+    #[serde(rename = "PlayCard", serialize_with = "serialize_u8_as_string")]
+    pub play_card: u8,
+    #[serde(rename = "NumOfCard", serialize_with = "serialize_u8_as_string")]
+    pub num_of_card: u8,
+}
+
+impl PlayedDashingAttack {
+    pub fn new(play: &PlayDashingAttack) -> Self {
+        Self {
+            typ: "Played",
+            from: "Server",
+            to: "Client",
+            message_id: "103",
+            move_card: play.move_card,
+            play_card: play.play_card,
+            num_of_card: play.num_of_card,
+        }
+    }
+}
+
+/// ラウンド終了の通知
 #[derive(Serialize, Debug)]
 pub struct RoundEnd {
     #[serde(rename = "Type")]
@@ -403,12 +514,14 @@ pub struct RoundEnd {
     pub score_0: u32,
     #[serde(rename = "Score1", serialize_with = "serialize_u32_as_string")]
     pub score_1: u32,
+    #[serde(rename = "Reason")]
+    pub reason: RoundEndReason,
     #[serde(rename = "Message")]
     pub message: &'static str,
 }
 
 impl RoundEnd {
-    pub fn hikiwake(board: &Board) -> Self {
+    pub fn hikiwake(board: &Board, reason: RoundEndReason) -> Self {
         Self {
             typ: "RoundEnd",
             from: "Server",
@@ -416,10 +529,11 @@ impl RoundEnd {
             round_winner: -1,
             score_0: board.score(PlayerID::Zero),
             score_1: board.score(PlayerID::One),
-            message: "a",
+            reason,
+            message: reason.message(),
         }
     }
-    pub fn win_lose(board: &Board, winner: PlayerID) -> Self {
+    pub fn win_lose(board: &Board, winner: PlayerID, reason: RoundEndReason) -> Self {
         Self {
             typ: "RoundEnd",
             from: "Server",
@@ -427,11 +541,13 @@ impl RoundEnd {
             round_winner: winner.denote() as i8,
             score_0: board.score(PlayerID::Zero),
             score_1: board.score(PlayerID::One),
-            message: "a",
+            reason,
+            message: reason.message(),
         }
     }
 }
 
+/// 試合終了の通知
 #[derive(Serialize, Debug)]
 pub struct GameEnd {
     #[serde(rename = "Type")]
@@ -440,8 +556,9 @@ pub struct GameEnd {
     from: &'static str,
     #[serde(rename = "To")]
     to: &'static str,
-    #[serde(rename = "Winner", serialize_with = "serialize_u8_as_string")]
-    pub winner: u8,
+    /// 勝者の番号。引き分けなら-1
+    #[serde(rename = "Winner", serialize_with = "serialize_i8_as_string")]
+    pub winner: i8,
     #[serde(rename = "Score0", serialize_with = "serialize_u32_as_string")]
     pub score_0: u32,
     #[serde(rename = "Score1", serialize_with = "serialize_u32_as_string")]
@@ -456,12 +573,115 @@ impl GameEnd {
             typ: "GameEnd",
             from: "Server",
             to: "Client",
-            winner: winner.denote(),
+            winner: winner.denote() as i8,
             score_0: board.score(PlayerID::Zero),
             score_1: board.score(PlayerID::One),
             message: "a",
         }
     }
+    pub fn draw(board: &Board) -> Self {
+        Self {
+            typ: "GameEnd",
+            from: "Server",
+            to: "Client",
+            winner: -1,
+            score_0: board.score(PlayerID::Zero),
+            score_1: board.score(PlayerID::One),
+            message: "引き分け",
+        }
+    }
+    pub fn forfeit(board: &Board, forfeit: Forfeit) -> Self {
+        Self {
+            typ: "GameEnd",
+            from: "Server",
+            to: "Client",
+            winner: forfeit.loser.opposite().denote() as i8,
+            score_0: board.score(PlayerID::Zero),
+            score_1: board.score(PlayerID::One),
+            message: forfeit.reason.message(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Disconnected {
+    #[serde(rename = "Type")]
+    typ: &'static str,
+    #[serde(rename = "From")]
+    from: &'static str,
+    #[serde(rename = "To")]
+    to: &'static str,
+    #[serde(rename = "ClientID")]
+    pub client_id: PlayerID,
+    #[serde(rename = "Message")]
+    pub message: &'static str,
+}
+
+impl Disconnected {
+    pub fn new(id: PlayerID) -> Self {
+        Self {
+            typ: "Disconnected",
+            from: "Server",
+            to: "Client",
+            client_id: id,
+            message: "相手が切断しました",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Timeout {
+    #[serde(rename = "Type")]
+    typ: &'static str,
+    #[serde(rename = "From")]
+    from: &'static str,
+    #[serde(rename = "To")]
+    to: &'static str,
+    #[serde(rename = "ClientID")]
+    pub client_id: PlayerID,
+    #[serde(rename = "Message")]
+    pub message: &'static str,
+}
+
+impl Timeout {
+    pub fn new(id: PlayerID, message: &'static str) -> Self {
+        Self {
+            typ: "Timeout",
+            from: "Server",
+            to: "Client",
+            client_id: id,
+            message,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Penalty {
+    #[serde(rename = "Type")]
+    typ: &'static str,
+    #[serde(rename = "From")]
+    from: &'static str,
+    #[serde(rename = "To")]
+    to: &'static str,
+    #[serde(rename = "ClientID")]
+    pub client_id: PlayerID,
+    #[serde(rename = "Policy")]
+    pub policy: &'static str,
+    #[serde(rename = "Message")]
+    pub message: &'static str,
+}
+
+impl Penalty {
+    pub fn new(id: PlayerID, policy: &'static str, message: &'static str) -> Self {
+        Self {
+            typ: "Penalty",
+            from: "Server",
+            to: "Client",
+            client_id: id,
+            policy,
+            message,
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -473,24 +693,51 @@ pub struct ServerError {
     from: &'static str,
     #[serde(rename = "To")]
     to: &'static str,
+    #[serde(rename = "Code")]
+    pub code: GameError,
     #[serde(rename = "Message")]
-    message: &'static str,
+    pub message: String,
     #[serde(rename = "MessageID")]
     message_id: &'static str,
 }
 
 impl ServerError {
-    pub fn new(string: &'static str) -> Self {
+    pub fn new(error: GameError) -> Self {
         Self {
             typ: "Error",
             from: "Server",
             to: "Client",
-            message: string,
+            code: error,
+            message: error.to_string(),
             message_id: "111",
         }
     }
 }
 
+/// 手番のプレイヤーが指せる手の一覧
+#[derive(Serialize, Debug)]
+pub struct LegalMoves {
+    #[serde(rename = "Type")]
+    typ: &'static str,
+    #[serde(rename = "From")]
+    from: &'static str,
+    #[serde(rename = "To")]
+    to: &'static str,
+    #[serde(rename = "Actions")]
+    pub actions: Vec<Action>,
+}
+
+impl LegalMoves {
+    pub fn new(actions: Vec<Action>) -> Self {
+        Self {
+            typ: "LegalMoves",
+            from: "Server",
+            to: "Client",
+            actions,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ParseMessageError {
     invalid_info: String,
@@ -514,31 +761,56 @@ pub struct ConnectionStart {
     to: &'static str,
     #[serde(rename = "ClientID")]
     pub client_id: PlayerID,
+    /// この試合のルール
+    #[serde(rename = "Rules")]
+    pub rules: RuleSet,
+    /// この試合の終わり方
+    #[serde(rename = "MatchFormat")]
+    pub format: MatchFormat,
 }
 
 impl ConnectionStart {
-    pub fn new(id: PlayerID) -> Self {
+    pub fn new(id: PlayerID, rules: RuleSet, format: MatchFormat) -> Self {
         Self {
             typ: "ConnectionStart",
             from: "Server",
             to: "Client",
             client_id: id,
+            rules,
+            format,
         }
     }
 }
 
+/// クライアントが最初に送る名前
 #[derive(Deserialize)]
 pub struct PlayerName {
     #[serde(rename = "Type")]
-    typ: &'static str,
+    typ: String,
     #[serde(rename = "From")]
-    from: &'static str,
+    _from: String,
     #[serde(rename = "To")]
-    to: &'static str,
+    _to: String,
     #[serde(rename = "Name")]
     name: String,
 }
 
+impl PlayerName {
+    pub fn parse(json: &str) -> Result<Self, Errors> {
+        let player_name = serde_json::from_str::<PlayerName>(json)?;
+        if player_name.typ != "PlayerName" {
+            Err(ParseMessageError {
+                invalid_info: json.to_string(),
+            })?
+        }
+        Ok(player_name)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct NameReceived {
     #[serde(rename = "Type")]
@@ -547,59 +819,64 @@ pub struct NameReceived {
     from: &'static str,
     #[serde(rename = "To")]
     to: &'static str,
+    #[serde(rename = "Name")]
+    pub name: String,
 }
 
 impl NameReceived {
-    pub fn new() -> Self {
+    pub fn new(name: &str) -> Self {
         Self {
             typ: "NameReceived",
             from: "Server",
             to: "Client",
+            name: name.to_string(),
         }
     }
 }
 
+/// 手の評価値。形式を確かめるだけで中身は使わないので、読まない項目は`_`で始める
 #[skip_serializing_none]
 #[derive(Deserialize)]
 pub struct Evaluation {
     #[serde(rename = "Type")]
-    typ: String,
+    _typ: String,
     #[serde(rename = "From")]
-    from: String,
+    _from: String,
     #[serde(rename = "To")]
-    to: String,
+    _to: String,
     #[serde(rename = "1F", default)]
-    eval_1f: Option<String>,
+    _eval_1f: Option<String>,
     #[serde(rename = "1B", default)]
-    eval_1b: Option<String>,
+    _eval_1b: Option<String>,
     #[serde(rename = "2F", default)]
-    eval_2f: Option<String>,
+    _eval_2f: Option<String>,
     #[serde(rename = "2B", default)]
-    eval_2b: Option<String>,
+    _eval_2b: Option<String>,
     #[serde(rename = "3F", default)]
-    eval_3f: Option<String>,
+    _eval_3f: Option<String>,
     #[serde(rename = "3B", default)]
-    eval_3b: Option<String>,
+    _eval_3b: Option<String>,
     #[serde(rename = "4F", default)]
-    eval_4f: Option<String>,
+    _eval_4f: Option<String>,
     #[serde(rename = "4B", default)]
-    eval_4b: Option<String>,
+    _eval_4b: Option<String>,
     #[serde(rename = "5F", default)]
-    eval_5f: Option<String>,
+    _eval_5f: Option<String>,
     #[serde(rename = "5B", default)]
-    eval_5b: Option<String>,
+    _eval_5b: Option<String>,
 }
 
+/// 移動の手(MessageID 101)
 #[derive(Deserialize)]
 pub struct PlayMovement {
     #[serde(rename = "Type")]
-    typ: String,
+    _typ: String,
     #[serde(rename = "From")]
-    from: String,
+    _from: String,
     #[serde(rename = "To")]
-    to: String,
+    _to: String,
     #[serde(rename = "MessageID")]
-    message_id: String,
+    _message_id: String,
     #[serde(
         rename = "PlayCard",
         deserialize_with = "deserialize_number_from_string"
@@ -610,6 +887,16 @@ pub struct PlayMovement {
 }
 
 impl PlayMovement {
+    pub fn new(play_card: u8, direction: Direction) -> Self {
+        Self {
+            _typ: "Play".to_string(),
+            _from: "Client".to_string(),
+            _to: "Server".to_string(),
+            _message_id: "101".to_string(),
+            play_card,
+            direction,
+        }
+    }
     pub fn play_card(&self) -> u8 {
         self.play_card
     }
@@ -618,16 +905,17 @@ impl PlayMovement {
     }
 }
 
+/// 攻撃の手(MessageID 102)。`num_of_card`枚の`play_card`で攻撃する
 #[derive(Deserialize)]
 pub struct PlayAttack {
     #[serde(rename = "Type")]
-    typ: String,
+    _typ: String,
     #[serde(rename = "From")]
-    from: String,
+    _from: String,
     #[serde(rename = "To")]
-    to: String,
+    _to: String,
     #[serde(rename = "MessageID")]
-    message_id: String,
+    _message_id: String,
     #[serde(
         rename = "PlayCard",
         deserialize_with = "deserialize_number_from_string"
@@ -641,6 +929,17 @@ pub struct PlayAttack {
 }
 
 impl PlayAttack {
+    pub fn new(play_card: u8, num_of_card: u8) -> Self {
+        Self {
+            _typ: "Play".to_string(),
+            _from: "Client".to_string(),
+            _to: "Server".to_string(),
+            _message_id: "102".to_string(),
+            play_card,
+            num_of_card,
+        }
+    }
+
     pub fn play_card(&self) -> u8 {
         self.play_card
     }
@@ -650,10 +949,66 @@ impl PlayAttack {
     }
 }
 
+/// 突進攻撃の手(MessageID 103、上級ルール)。`move_card`で前に出てから`num_of_card`枚の`play_card`で攻撃する
+#[derive(Deserialize)]
+pub struct PlayDashingAttack {
+    #[serde(rename = "Type")]
+    _typ: String,
+    #[serde(rename = "From")]
+    _from: String,
+    #[serde(rename = "To")]
+    _to: String,
+    #[serde(rename = "MessageID")]
+    _message_id: String,
+    #[serde(
+        rename = "MoveCard",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    move_card: u8,
+    #[serde(
+        rename = "PlayCard",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    play_card: u8,
+    #[serde(
+        rename = "NumOfCard",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    num_of_card: u8,
+}
+
+impl PlayDashingAttack {
+    pub fn new(move_card: u8, play_card: u8, num_of_card: u8) -> Self {
+        Self {
+            _typ: "Play".to_string(),
+            _from: "Client".to_string(),
+            _to: "Server".to_string(),
+            _message_id: "103".to_string(),
+            move_card,
+            play_card,
+            num_of_card,
+        }
+    }
+
+    pub fn move_card(&self) -> u8 {
+        self.move_card
+    }
+
+    pub fn play_card(&self) -> u8 {
+        self.play_card
+    }
+
+    pub fn num_of_card(&self) -> u8 {
+        self.num_of_card
+    }
+}
+
+/// 手番中にクライアントから届くメッセージ
 pub enum Messages {
     Eval(Box<Evaluation>),
     PlayM(PlayMovement),
     PlayA(PlayAttack),
+    PlayD(PlayDashingAttack),
 }
 
 impl Messages {
@@ -675,6 +1030,7 @@ impl Messages {
                 match message_id {
                     "101" => Ok(Messages::PlayM(serde_json::from_str(json)?)),
                     "102" => Ok(Messages::PlayA(serde_json::from_str(json)?)),
+                    "103" => Ok(Messages::PlayD(serde_json::from_str(json)?)),
                     _ => Err(ParseMessageError {
                         invalid_info: json.to_string(),
                     })?,
@@ -685,4 +1041,93 @@ impl Messages {
             })?,
         }
     }
+
+    /// 手のメッセージなら指す手にする。`Evaluation`なら`None`
+    pub fn action(&self) -> Option<Action> {
+        match self {
+            Messages::Eval(_) => None,
+            Messages::PlayM(movement) => Some(Action::Move(Movement {
+                card: movement.play_card(),
+                direction: movement.direction(),
+            })),
+            Messages::PlayA(attack) => Some(Action::Attack(Attack {
+                card: attack.play_card(),
+                quantity: attack.num_of_card(),
+            })),
+            Messages::PlayD(dash) => Some(Action::DashingAttack(DashingAttack {
+                move_card: dash.move_card(),
+                card: dash.play_card(),
+                quantity: dash.num_of_card(),
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameManager;
+
+    #[test]
+    fn hand_info_lists_as_many_keys_as_cards() {
+        let json = serde_json::to_string(&HandInfo::from_vec(&[1, 3, 5])).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Type":"HandInfo","From":"Server","To":"Client","Hand1":"1","Hand2":"3","Hand3":"5"}"#
+        );
+    }
+
+    #[test]
+    fn actions_are_tagged_with_their_message_id() {
+        let actions = [
+            Action::Move(Movement {
+                card: 2,
+                direction: Direction::Back,
+            }),
+            Action::Attack(Attack {
+                card: 3,
+                quantity: 2,
+            }),
+        ];
+        let json = serde_json::to_string(&actions).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"MessageID":"101","PlayCard":"2","Direction":"B"},{"MessageID":"102","PlayCard":"3","NumOfCard":"2"}]"#
+        );
+        assert_eq!(serde_json::from_str::<Vec<Action>>(&json).unwrap(), actions);
+    }
+
+    #[test]
+    fn dashing_attack_is_tagged_103() {
+        let action = Action::DashingAttack(DashingAttack {
+            move_card: 2,
+            card: 3,
+            quantity: 1,
+        });
+        let json = serde_json::to_string(&action).unwrap();
+        assert_eq!(
+            json,
+            r#"{"MessageID":"103","MoveCard":"2","PlayCard":"3","NumOfCard":"1"}"#
+        );
+        assert_eq!(serde_json::from_str::<Action>(&json).unwrap(), action);
+    }
+
+    #[test]
+    fn drawn_game_reports_winner_minus_one() {
+        let game_manager = GameManager::new(1, 0);
+        let json = serde_json::to_string(&GameEnd::draw(game_manager.board())).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Type":"GameEnd","From":"Server","To":"Client","Winner":"-1","Score0":"0","Score1":"0","Message":"引き分け"}"#
+        );
+    }
+
+    #[test]
+    fn server_error_carries_the_variant_name_as_its_code() {
+        let json = serde_json::to_string(&ServerError::new(GameError::NotInHand)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Type":"Error","From":"Server","To":"Client","Code":"NotInHand","Message":"そのカードは持ってません!","MessageID":"111"}"#
+        );
+    }
 }
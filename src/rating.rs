@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Mutex,
+};
+
+use engarde_server::protocol::PlayerID;
+use serde::{Deserialize, Serialize};
+
+use crate::record::timestamp;
+
+/// 初めて対戦したプレイヤーのレーティング
+const INITIAL_RATING: f64 = 1500.0;
+/// 1試合で動くレーティングの最大幅
+const K_FACTOR: f64 = 32.0;
+
+/// 同じサーバーで試合が同時に終わっても、読んでから書くまでの間に割り込まれないようにする
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// プレイヤー1人分の成績
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct Rating {
+    pub rating: f64,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    #[serde(default)]
+    pub draws: u32,
+    /// 最後に対戦したUNIX秒
+    pub last_played: u64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            games: 0,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            last_played: 0,
+        }
+    }
+}
+
+/// `PlayerName`ごとの成績。ファイルがなければ空
+pub fn load(path: &Path) -> io::Result<BTreeMap<String, Rating>> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}を読めません: {}", path.display(), e),
+            )
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 書きかけのファイルが残らないよう、一時ファイルに書いてから置き換える
+fn save(path: &Path, ratings: &BTreeMap<String, Rating>) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, serde_json::to_string_pretty(ratings)?)?;
+    fs::rename(temporary, path)
+}
+
+/// 試合の結果でEloレーティングを更新し、席順に更新後の成績を返す。`winner`が`None`なら引き分け
+pub fn update(
+    path: &Path,
+    players: [&str; 2],
+    winner: Option<PlayerID>,
+) -> io::Result<[Rating; 2]> {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut ratings = load(path)?;
+    let mut updated = players.map(|name| ratings.get(name).cloned().unwrap_or_default());
+    // p0の期待勝率
+    let expected = 1.0 / (1.0 + 10f64.powf((updated[1].rating - updated[0].rating) / 400.0));
+    let score = match winner {
+        Some(PlayerID::Zero) => 1.0,
+        Some(PlayerID::One) => 0.0,
+        None => 0.5,
+    };
+    let delta = K_FACTOR * (score - expected);
+    let now = timestamp();
+    for (id, rating) in [PlayerID::Zero, PlayerID::One]
+        .into_iter()
+        .zip(&mut updated)
+    {
+        if id == PlayerID::Zero {
+            rating.rating += delta;
+        } else {
+            rating.rating -= delta;
+        }
+        rating.games += 1;
+        match winner {
+            Some(winner) if winner == id => rating.wins += 1,
+            Some(_) => rating.losses += 1,
+            None => rating.draws += 1,
+        }
+        rating.last_played = now;
+    }
+    for (name, rating) in players.iter().zip(&updated) {
+        ratings.insert(name.to_string(), rating.clone());
+    }
+    save(path, &ratings)?;
+    Ok(updated)
+}
+
+pub fn leaderboard(path: &Path, top: Option<usize>) -> io::Result<()> {
+    let ratings = load(path)?;
+    if ratings.is_empty() {
+        println!("まだ試合の記録がありません");
+        return Ok(());
+    }
+    let mut rows = ratings.iter().collect::<Vec<_>>();
+    rows.sort_by(|a, b| b.1.rating.total_cmp(&a.1.rating).then_with(|| a.0.cmp(b.0)));
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let now = timestamp();
+    println!(
+        "順位 {:<width$} レーティング 試合 勝 負 分 最終対戦",
+        "名前"
+    );
+    for (rank, (name, rating)) in rows.into_iter().take(top.unwrap_or(usize::MAX)).enumerate() {
+        let days = now.saturating_sub(rating.last_played) / (24 * 60 * 60);
+        println!(
+            "{:>4} {:<width$} {:>12.1} {:>4} {:>2} {:>2} {:>2} {}",
+            rank + 1,
+            name,
+            rating.rating,
+            rating.games,
+            rating.wins,
+            rating.losses,
+            rating.draws,
+            if days == 0 {
+                "今日".to_string()
+            } else {
+                format!("{}日前", days)
+            }
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// テストごとに別の、まだないファイル
+    fn ratings_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "engarde_ratings_{}_{}.json",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn win_between_equal_ratings_moves_half_the_k_factor() {
+        let path = ratings_file("win");
+        let [winner, loser] = update(&path, ["alice", "bob"], Some(PlayerID::Zero)).unwrap();
+        assert_eq!(winner.rating, 1516.0);
+        assert_eq!(loser.rating, 1484.0);
+        assert_eq!((winner.games, winner.wins, winner.losses), (1, 1, 0));
+        assert_eq!((loser.games, loser.wins, loser.losses), (1, 0, 1));
+        let saved = load(&path).unwrap();
+        assert_eq!(saved["alice"].rating, 1516.0);
+        assert_eq!(saved["bob"].rating, 1484.0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn draw_between_equal_ratings_changes_nothing() {
+        let path = ratings_file("draw");
+        let [first, second] = update(&path, ["alice", "bob"], None).unwrap();
+        assert_eq!(first.rating, INITIAL_RATING);
+        assert_eq!(second.rating, INITIAL_RATING);
+        assert_eq!((first.draws, second.draws), (1, 1));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_an_empty_table() {
+        assert!(load(&ratings_file("missing")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use engarde_server::{
+    errors::GameError,
+    game::{Forfeit, GameManager, Kekka, RoundEndReason},
+    protocol::{Direction, PlayerID},
+    rules::{MatchFormat, RuleSet},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "Result")]
+pub enum Outcome {
+    Continue,
+    RoundEnd {
+        #[serde(rename = "Winner")]
+        winner: Option<PlayerID>,
+        #[serde(rename = "Reason")]
+        reason: RoundEndReason,
+    },
+}
+
+impl From<&Kekka> for Outcome {
+    fn from(value: &Kekka) -> Self {
+        match value {
+            Kekka::Continue => Self::Continue,
+            Kekka::REnd(winner, reason) => Self::RoundEnd {
+                winner: *winner,
+                reason: *reason,
+            },
+        }
+    }
+}
+
+/// 棋譜の1行分。`Event`キーで種類を表す
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "Event", rename_all_fields = "PascalCase")]
+pub enum Event {
+    MatchStart {
+        match_id: u64,
+        timestamp: u64,
+        players: [String; 2],
+        seed: u64,
+        /// 以前の棋譜の`MaxRound`もそのまま読める
+        #[serde(flatten)]
+        format: MatchFormat,
+        /// 標準ルールの棋譜には書かない
+        #[serde(default, skip_serializing_if = "RuleSet::is_standard")]
+        rules: RuleSet,
+    },
+    RoundStart {
+        round: u32,
+        first_player: PlayerID,
+        hand0: Vec<u8>,
+        hand1: Vec<u8>,
+        deck: Vec<u8>,
+    },
+    Move {
+        player: PlayerID,
+        card: u8,
+        direction: Direction,
+        #[serde(flatten)]
+        outcome: Outcome,
+    },
+    Attack {
+        player: PlayerID,
+        card: u8,
+        num_of_card: u8,
+        #[serde(flatten)]
+        outcome: Outcome,
+    },
+    DashingAttack {
+        player: PlayerID,
+        move_card: u8,
+        card: u8,
+        num_of_card: u8,
+        #[serde(flatten)]
+        outcome: Outcome,
+    },
+    Error {
+        player: PlayerID,
+        #[serde(default)]
+        code: Option<GameError>,
+        message: String,
+    },
+    Penalty {
+        player: PlayerID,
+        policy: String,
+    },
+    Timeout {
+        player: PlayerID,
+    },
+    RoundEnd {
+        round: u32,
+        winner: Option<PlayerID>,
+        reason: RoundEndReason,
+        score0: u32,
+        score1: u32,
+    },
+    GameEnd {
+        winner: Option<PlayerID>,
+        score0: u32,
+        score1: u32,
+        forfeit: Option<String>,
+    },
+}
+
+impl Event {
+    pub fn round_start(game_manager: &GameManager) -> Self {
+        Self::RoundStart {
+            round: game_manager.round(),
+            first_player: game_manager.board().current_player(),
+            hand0: game_manager.player(PlayerID::Zero).hand().to_vec(),
+            hand1: game_manager.player(PlayerID::One).hand().to_vec(),
+            deck: game_manager.board().yamafuda().to_vec(),
+        }
+    }
+
+    pub fn round_end(
+        game_manager: &GameManager,
+        winner: Option<PlayerID>,
+        reason: RoundEndReason,
+    ) -> Self {
+        Self::RoundEnd {
+            round: game_manager.round(),
+            winner,
+            reason,
+            score0: game_manager.board().score(PlayerID::Zero),
+            score1: game_manager.board().score(PlayerID::One),
+        }
+    }
+
+    pub fn game_end(game_manager: &GameManager, winner: Option<PlayerID>) -> Self {
+        Self::GameEnd {
+            winner,
+            score0: game_manager.board().score(PlayerID::Zero),
+            score1: game_manager.board().score(PlayerID::One),
+            forfeit: game_manager
+                .forfeited()
+                .map(|Forfeit { loser, reason }| format!("p{}の{}", loser.denote(), reason)),
+        }
+    }
+}
+
+/// 1試合分の棋譜をJSON Linesで書き出す。ディレクトリ未指定なら何もしない
+pub struct Record {
+    writer: Option<BufWriter<File>>,
+}
+
+impl Record {
+    pub fn create(log_dir: Option<&Path>, match_id: u64) -> io::Result<Self> {
+        let writer = match log_dir {
+            Some(log_dir) => {
+                fs::create_dir_all(log_dir)?;
+                let file = File::create(log_dir.join(format!(
+                    "match_{}_{}.jsonl",
+                    timestamp(),
+                    match_id
+                )))?;
+                Some(BufWriter::new(file))
+            }
+            None => None,
+        };
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, event: &Event) -> io::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            serde_json::to_writer(&mut *writer, event)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_start(rules: RuleSet) -> Event {
+        Event::MatchStart {
+            match_id: 0,
+            timestamp: 0,
+            players: ["alice".to_string(), "bob".to_string()],
+            seed: 1,
+            format: MatchFormat::max_round(100),
+            rules,
+        }
+    }
+
+    fn rules_of(event: Event) -> RuleSet {
+        match event {
+            Event::MatchStart { rules, .. } => rules,
+            other => panic!("MatchStartのはずが{:?}", other),
+        }
+    }
+
+    #[test]
+    fn standard_rules_are_left_out_and_read_back_as_default() {
+        let json = serde_json::to_string(&match_start(RuleSet::default())).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Event":"MatchStart","MatchId":0,"Timestamp":0,"Players":["alice","bob"],"Seed":1,"MaxRound":100}"#
+        );
+        let event = serde_json::from_str::<Event>(&json).unwrap();
+        assert!(rules_of(event).is_standard());
+    }
+
+    #[test]
+    fn other_rules_are_written_and_read_back() {
+        let rules = RuleSet {
+            track_length: 29,
+            ..RuleSet::default()
+        };
+        let json = serde_json::to_string(&match_start(rules.clone())).unwrap();
+        assert!(json.contains(r#""Rules":{"TrackLength":29,"#));
+        let event = serde_json::from_str::<Event>(&json).unwrap();
+        assert_eq!(rules_of(event), rules);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use engarde_server::{
+    errors::GameError,
+    game::{GameManager, Kekka},
+    protocol::{PlayAttack, PlayDashingAttack, PlayMovement, PlayerID},
+};
+
+use crate::record::{Event, Outcome};
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(usize, serde_json::Error),
+    Mismatch(usize, String),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(line, e) => write!(f, "{}行目: 棋譜を読めません: {}", line, e),
+            Self::Mismatch(line, message) => {
+                write!(f, "{}行目: 記録と一致しません: {}", line, message)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn render(game_manager: &GameManager) -> String {
+    let board = game_manager.board();
+    let track = (1..=board.most_right_side())
+        .map(|pos| {
+            if pos == board.pos(PlayerID::Zero) {
+                '0'
+            } else if pos == board.pos(PlayerID::One) {
+                '1'
+            } else {
+                '.'
+            }
+        })
+        .collect::<String>();
+    format!(
+        "|{}| 山札{}枚 p0手札{:?} p1手札{:?} 得点 {}-{}",
+        track,
+        board.yamafuda().len(),
+        game_manager.player(PlayerID::Zero).hand(),
+        game_manager.player(PlayerID::One).hand(),
+        board.score(PlayerID::Zero),
+        board.score(PlayerID::One)
+    )
+}
+
+struct Replayer {
+    game_manager: Option<GameManager>,
+    last: Option<Kekka>,
+    quiet: bool,
+}
+
+impl Replayer {
+    fn game_manager(&mut self, line: usize) -> Result<&mut GameManager, ReplayError> {
+        self.game_manager
+            .as_mut()
+            .ok_or_else(|| ReplayError::Mismatch(line, "MatchStartがありません".to_string()))
+    }
+
+    fn show(&mut self, line: usize, label: String) -> Result<(), ReplayError> {
+        let board = render(self.game_manager(line)?);
+        if !self.quiet {
+            println!("{:>4}: {}\n      {}", line, label, board);
+        }
+        Ok(())
+    }
+
+    fn play(
+        &mut self,
+        line: usize,
+        player: PlayerID,
+        kekka: Result<Kekka, GameError>,
+        outcome: Outcome,
+    ) -> Result<(), ReplayError> {
+        let kekka = kekka.map_err(|e| ReplayError::Mismatch(line, e.to_string()))?;
+        let replayed = Outcome::from(&kekka);
+        if replayed != outcome {
+            return Err(ReplayError::Mismatch(
+                line,
+                format!("結果が{:?}になりました(記録は{:?})", replayed, outcome),
+            ));
+        }
+        if let Kekka::Continue = kekka {
+            *self.game_manager(line)?.current_playerid_mut() = player.opposite();
+        }
+        self.last = Some(kekka);
+        Ok(())
+    }
+
+    fn check_turn(&mut self, line: usize, player: PlayerID) -> Result<(), ReplayError> {
+        let current = self.game_manager(line)?.board().current_player();
+        if current != player {
+            return Err(ReplayError::Mismatch(
+                line,
+                format!("p{}の手番のはずです", current.denote()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, line: usize, event: Event) -> Result<(), ReplayError> {
+        match event {
+            Event::MatchStart {
+                players,
+                seed,
+                format,
+                rules,
+                ..
+            } => {
+                rules
+                    .validate()
+                    .and_then(|()| format.validate())
+                    .map_err(|e| ReplayError::Mismatch(line, e.to_string()))?;
+                self.game_manager = Some(GameManager::with_format(format, seed, rules));
+                self.show(
+                    line,
+                    format!(
+                        "試合開始 p0: {}, p1: {} ({}, シード値: {})",
+                        players[0], players[1], format, seed
+                    ),
+                )?;
+            }
+            Event::RoundStart {
+                round,
+                first_player,
+                hand0,
+                hand1,
+                deck,
+            } => {
+                let game_manager = self.game_manager(line)?;
+                if game_manager.round() != round
+                    || game_manager.board().current_player() != first_player
+                    || game_manager.player(PlayerID::Zero).hand() != hand0
+                    || game_manager.player(PlayerID::One).hand() != hand1
+                    || game_manager.board().yamafuda() != deck
+                {
+                    return Err(ReplayError::Mismatch(
+                        line,
+                        format!("ラウンド{}の配り方が違います", round),
+                    ));
+                }
+                self.last = None;
+                self.show(line, format!("ラウンド{}開始", round))?;
+            }
+            Event::Move {
+                player,
+                card,
+                direction,
+                outcome,
+            } => {
+                self.check_turn(line, player)?;
+                let kekka = self
+                    .game_manager(line)?
+                    .play_movement(player, &PlayMovement::new(card, direction));
+                self.play(line, player, kekka, outcome)?;
+                self.show(line, format!("p{}: {}{}", player.denote(), card, direction))?;
+            }
+            Event::Attack {
+                player,
+                card,
+                num_of_card,
+                outcome,
+            } => {
+                self.check_turn(line, player)?;
+                let kekka = self
+                    .game_manager(line)?
+                    .play_attack(player, &PlayAttack::new(card, num_of_card));
+                self.play(line, player, kekka, outcome)?;
+                self.show(
+                    line,
+                    format!("p{}: {}を{}枚で攻撃", player.denote(), card, num_of_card),
+                )?;
+            }
+            Event::DashingAttack {
+                player,
+                move_card,
+                card,
+                num_of_card,
+                outcome,
+            } => {
+                self.check_turn(line, player)?;
+                let kekka = self.game_manager(line)?.play_dashing_attack(
+                    player,
+                    &PlayDashingAttack::new(move_card, card, num_of_card),
+                );
+                self.play(line, player, kekka, outcome)?;
+                self.show(
+                    line,
+                    format!(
+                        "p{}: {}前進して{}を{}枚で突進攻撃",
+                        player.denote(),
+                        move_card,
+                        card,
+                        num_of_card
+                    ),
+                )?;
+            }
+            Event::Error {
+                player, message, ..
+            } => {
+                if !self.quiet {
+                    println!("{:>4}: p{}へのエラー: {}", line, player.denote(), message);
+                }
+            }
+            Event::Penalty { player, policy } => {
+                if !self.quiet {
+                    println!("{:>4}: p{}に罰則: {}", line, player.denote(), policy);
+                }
+            }
+            Event::Timeout { player } => {
+                if !self.quiet {
+                    println!("{:>4}: p{}が時間切れ", line, player.denote());
+                }
+            }
+            Event::RoundEnd {
+                round,
+                winner,
+                reason,
+                score0,
+                score1,
+            } => {
+                let last = self.last.take();
+                let game_manager = self.game_manager(line)?;
+                // 手の結果でなくラウンドが終わった場合は罰則か時間切れによる判定負け
+                match (last, winner) {
+                    (None | Some(Kekka::Continue), Some(winner)) => {
+                        game_manager.lose_round(winner.opposite(), reason);
+                    }
+                    (Some(Kekka::REnd(_, replayed)), _) if replayed != reason => {
+                        return Err(ReplayError::Mismatch(
+                            line,
+                            format!("ラウンド{}の終わり方が違います", round),
+                        ));
+                    }
+                    _ => {}
+                }
+                if game_manager.board().score(PlayerID::Zero) != score0
+                    || game_manager.board().score(PlayerID::One) != score1
+                {
+                    return Err(ReplayError::Mismatch(
+                        line,
+                        format!("ラウンド{}終了時の得点が違います", round),
+                    ));
+                }
+                self.show(
+                    line,
+                    match winner {
+                        Some(winner) => format!(
+                            "ラウンド{}終了 p{}の勝ち ({})",
+                            round,
+                            winner.denote(),
+                            reason
+                        ),
+                        None => format!("ラウンド{}終了 引き分け ({})", round, reason),
+                    },
+                )?;
+                let game_manager = self.game_manager(line)?;
+                game_manager.reset_round();
+                if game_manager.ended().is_none() {
+                    game_manager.change_first_player();
+                }
+            }
+            Event::GameEnd {
+                winner,
+                score0,
+                score1,
+                forfeit,
+            } => {
+                let game_manager = self.game_manager(line)?;
+                let scores = (
+                    game_manager.board().score(PlayerID::Zero),
+                    game_manager.board().score(PlayerID::One),
+                );
+                if forfeit.is_none() && game_manager.ended() != Some(winner) {
+                    return Err(ReplayError::Mismatch(
+                        line,
+                        match winner {
+                            Some(winner) => format!("p{}の勝ちになりません", winner.denote()),
+                            None => "引き分けになりません".to_string(),
+                        },
+                    ));
+                }
+                if scores != (score0, score1) {
+                    return Err(ReplayError::Mismatch(
+                        line,
+                        "最終得点が違います".to_string(),
+                    ));
+                }
+                println!(
+                    "試合終了 {} {}-{}{}",
+                    match winner {
+                        Some(winner) => format!("p{}の勝ち", winner.denote()),
+                        None => "引き分け".to_string(),
+                    },
+                    score0,
+                    score1,
+                    forfeit.map(|f| format!(" ({})", f)).unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn replay(path: &Path, quiet: bool) -> Result<(), ReplayError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut replayer = Replayer {
+        game_manager: None,
+        last: None,
+        quiet,
+    };
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|e| ReplayError::Parse(index + 1, e))?;
+        replayer.step(index + 1, event)?;
+    }
+    println!("棋譜どおりに再生できました");
+    Ok(())
+}
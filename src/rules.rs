@@ -0,0 +1,326 @@
+//! 盤の長さ・山札・手札の枚数などのルールと、試合の終わり方の設定
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::RuleError;
+use crate::game::{MOST_LEFT_SIDE, MOST_RIGHT_SIDE};
+
+/// 変えられるルール。JSONの設定ファイルから読み、書かれていない項目は標準ルールのまま
+///
+/// ```json
+/// {"TrackLength": 29, "CardValues": [1, 2, 3, 4, 5, 6], "Copies": 5, "HandSize": 6, "Advanced": true, "Tiebreak": "SuddenDeath"}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default, deny_unknown_fields)]
+pub struct RuleSet {
+    /// 盤のマスの数。p0は1マス目、p1は最後のマスから始まる
+    pub track_length: u8,
+    /// 山札に入れるカードの数字
+    pub card_values: Vec<u8>,
+    /// 数字ごとのカードの枚数
+    pub copies: u8,
+    /// 手札の枚数
+    pub hand_size: u8,
+    /// 上級ルール。前に出てから攻撃する突進攻撃ができ、受け流した側は次の手番で後ろへ下がる
+    pub advanced: bool,
+    /// 試合が終わる得点で両者が並んだときの扱い
+    pub tiebreak: Tiebreak,
+}
+
+/// 試合の終わり方。指定した条件のどれかを満たしたラウンドの終わりで試合が終わる
+///
+/// ```json
+/// {"FirstTo": 5, "RoundLimit": 9}
+/// ```
+///
+/// 棋譜の`MatchStart`には平らに展開して書くので、知らない項目があっても拒まない
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MatchFormat {
+    /// 両者の合計得点がこの値に達したら終わる。引き分けのラウンドは数えない
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_round: Option<u32>,
+    /// どちらかがこのラウンド数に勝ったら終わる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_to: Option<u32>,
+    /// 引き分けを含めてこのラウンド数を終えたら終わる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round_limit: Option<u32>,
+}
+
+impl MatchFormat {
+    /// 両者の合計得点が`max_round`に達したら終わる形式
+    pub fn max_round(max_round: u32) -> Self {
+        Self {
+            max_round: Some(max_round),
+            ..Self::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), RuleError> {
+        let limits = [self.max_round, self.first_to, self.round_limit];
+        if limits.iter().all(Option::is_none) {
+            return Err(RuleError::Invalid("試合の終わる条件がありません"));
+        }
+        if limits.contains(&Some(0)) {
+            return Err(RuleError::Invalid("試合の終わる条件は1以上にしてください"));
+        }
+        Ok(())
+    }
+
+    /// `scores`の得点で`rounds`ラウンドを終えたとき、試合の終わる条件を満たしたか
+    pub fn reached(&self, scores: [u32; 2], rounds: u32) -> bool {
+        self.max_round
+            .is_some_and(|max_round| scores[0] + scores[1] >= max_round)
+            || self
+                .first_to
+                .is_some_and(|first_to| scores[0].max(scores[1]) >= first_to)
+            || self.round_limit.is_some_and(|limit| rounds >= limit)
+    }
+}
+
+impl Display for MatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limits = Vec::new();
+        if let Some(max_round) = self.max_round {
+            limits.push(format!("合計{}点", max_round));
+        }
+        if let Some(first_to) = self.first_to {
+            limits.push(format!("{}勝先取", first_to));
+        }
+        if let Some(round_limit) = self.round_limit {
+            limits.push(format!("{}ラウンド", round_limit));
+        }
+        write!(f, "{}", limits.join("か"))
+    }
+}
+
+/// 試合が終わる得点で両者が並んだときの扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tiebreak {
+    /// 引き分けで試合を終える
+    #[default]
+    Draw,
+    /// どちらかがラウンドに勝つまで続ける
+    SuddenDeath,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self {
+            track_length: MOST_RIGHT_SIDE,
+            card_values: (1..=5).collect(),
+            copies: 5,
+            hand_size: 5,
+            advanced: false,
+            tiebreak: Tiebreak::Draw,
+        }
+    }
+}
+
+impl RuleSet {
+    /// JSONから読み、遊べるルールか確かめる
+    pub fn from_json(json: &str) -> Result<Self, RuleError> {
+        let rules = serde_json::from_str::<Self>(json)?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    pub fn validate(&self) -> Result<(), RuleError> {
+        let mut values = self.card_values.clone();
+        values.sort_unstable();
+        values.dedup();
+        if values.is_empty() {
+            return Err(RuleError::Invalid("カードの数字がありません"));
+        }
+        if values.len() != self.card_values.len() {
+            return Err(RuleError::Invalid("カードの数字が重複しています"));
+        }
+        if values[0] == 0 {
+            return Err(RuleError::Invalid("カードの数字は1以上にしてください"));
+        }
+        if self.track_length <= MOST_LEFT_SIDE + 1 {
+            return Err(RuleError::Invalid("盤が短すぎます"));
+        }
+        // 位置にカードの数字を足しても溢れないようにする
+        if self.track_length.checked_add(self.max_card()).is_none() {
+            return Err(RuleError::Invalid(
+                "盤の長さとカードの数字の合計は255以下にしてください",
+            ));
+        }
+        if self.copies == 0 || self.hand_size == 0 {
+            return Err(RuleError::Invalid(
+                "カードの枚数と手札の枚数は1以上にしてください",
+            ));
+        }
+        let deck = self.card_values.len() * self.copies as usize;
+        if deck > u8::MAX as usize {
+            return Err(RuleError::Invalid("山札は255枚以下にしてください"));
+        }
+        if deck <= self.hand_size as usize * 2 {
+            return Err(RuleError::Invalid("手札を配ると山札が残りません"));
+        }
+        Ok(())
+    }
+
+    /// シャッフル前の山札。数字の順に`copies`枚ずつ並ぶ
+    pub fn deck(&self) -> Vec<u8> {
+        self.card_values
+            .iter()
+            .flat_map(|&value| std::iter::repeat_n(value, self.copies as usize))
+            .collect()
+    }
+
+    /// 一番大きいカードの数字。相手との距離がこれより大きければ攻撃は届かない
+    pub fn max_card(&self) -> u8 {
+        self.card_values.iter().copied().max().unwrap_or_default()
+    }
+
+    /// 標準ルールかどうか
+    pub fn is_standard(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(rules: RuleSet) -> &'static str {
+        match rules.validate() {
+            Err(RuleError::Invalid(message)) => message,
+            other => panic!(
+                "遊べないはずのルールが{:?}になりました",
+                other.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn standard_rules_are_valid() {
+        assert!(RuleSet::default().validate().is_ok());
+        let rules = RuleSet::from_json(r#"{"TrackLength": 29, "Advanced": true}"#).unwrap();
+        assert_eq!(rules.track_length, 29);
+        assert_eq!(rules.card_values, RuleSet::default().card_values);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(RuleSet::from_json(r#"{"TrackLenght": 29}"#).is_err());
+    }
+
+    #[test]
+    fn deck_must_outlast_two_hands() {
+        let rules = RuleSet {
+            card_values: vec![1, 2],
+            ..RuleSet::default()
+        };
+        // 2種類5枚ずつの10枚を5枚ずつ配ると残らない
+        assert_eq!(invalid(rules.clone()), "手札を配ると山札が残りません");
+        let rules = RuleSet {
+            hand_size: 4,
+            ..rules
+        };
+        assert!(rules.validate().is_ok());
+    }
+
+    #[test]
+    fn deck_is_at_most_255_cards() {
+        let rules = RuleSet {
+            card_values: (1..=6).collect(),
+            copies: 43,
+            ..RuleSet::default()
+        };
+        assert_eq!(invalid(rules), "山札は255枚以下にしてください");
+    }
+
+    #[test]
+    fn track_and_card_must_not_overflow() {
+        let rules = RuleSet {
+            track_length: 250,
+            card_values: (1..=6).collect(),
+            ..RuleSet::default()
+        };
+        assert_eq!(
+            invalid(rules.clone()),
+            "盤の長さとカードの数字の合計は255以下にしてください"
+        );
+        let rules = RuleSet {
+            track_length: 249,
+            ..rules
+        };
+        assert!(rules.validate().is_ok());
+    }
+
+    #[test]
+    fn card_values_must_be_distinct_and_positive() {
+        let duplicated = RuleSet {
+            card_values: vec![1, 2, 2],
+            ..RuleSet::default()
+        };
+        assert_eq!(invalid(duplicated), "カードの数字が重複しています");
+        let zero = RuleSet {
+            card_values: vec![0, 1, 2],
+            ..RuleSet::default()
+        };
+        assert_eq!(invalid(zero), "カードの数字は1以上にしてください");
+        let short = RuleSet {
+            track_length: 2,
+            ..RuleSet::default()
+        };
+        assert_eq!(invalid(short), "盤が短すぎます");
+    }
+
+    #[test]
+    fn match_format_needs_a_positive_limit() {
+        assert!(MatchFormat::default().validate().is_err());
+        let zero = MatchFormat {
+            first_to: Some(0),
+            ..MatchFormat::default()
+        };
+        assert!(zero.validate().is_err());
+        assert!(MatchFormat::max_round(1).validate().is_ok());
+    }
+
+    #[test]
+    fn max_round_counts_only_points() {
+        let format = MatchFormat::max_round(5);
+        assert!(!format.reached([2, 2], 10));
+        assert!(format.reached([3, 2], 5));
+    }
+
+    #[test]
+    fn first_to_counts_the_leader() {
+        let format = MatchFormat {
+            first_to: Some(3),
+            ..MatchFormat::default()
+        };
+        assert!(!format.reached([2, 2], 4));
+        assert!(format.reached([1, 3], 4));
+    }
+
+    #[test]
+    fn round_limit_counts_drawn_rounds() {
+        let format = MatchFormat {
+            round_limit: Some(3),
+            ..MatchFormat::default()
+        };
+        assert!(!format.reached([1, 0], 2));
+        assert!(format.reached([0, 0], 3));
+    }
+
+    #[test]
+    fn either_limit_ends_the_match() {
+        let format = MatchFormat {
+            max_round: None,
+            first_to: Some(3),
+            round_limit: Some(5),
+        };
+        assert!(format.reached([3, 0], 3));
+        assert!(format.reached([2, 2], 5));
+        assert!(!format.reached([2, 2], 4));
+        assert_eq!(format.to_string(), "3勝先取か5ラウンド");
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::Instant,
+};
+
+use engarde_server::{
+    bot::Bot,
+    game::{derive_seed, GameManager, Kekka, RoundEndReason},
+    protocol::PlayerID,
+    rules::{MatchFormat, RuleSet},
+};
+use rand::Rng;
+
+use crate::{cli::SimulateArgs, duplicate_result};
+
+/// 95%信頼区間のz値
+const Z: f64 = 1.96;
+
+/// 集計した結果。試合ごとに作り、スレッドごと・全体へとまとめる
+#[derive(Default)]
+struct Stats {
+    games: u64,
+    /// `bot0`・`bot1`それぞれの勝った試合数
+    wins: [u64; 2],
+    /// p0の席が勝った試合数
+    p0_wins: u64,
+    /// 引き分けた試合数
+    game_draws: u64,
+    rounds: u64,
+    /// 先手が勝ったラウンド数
+    first_player_wins: u64,
+    draws: u64,
+    turns: u64,
+    reasons: HashMap<RoundEndReason, u64>,
+    /// 同じ山札で席を入れ替えた2試合の組の数と、`bot0`・`bot1`それぞれが合計得点で勝った組の数
+    pairs: u64,
+    pair_wins: [u64; 2],
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        self.games += other.games;
+        self.wins[0] += other.wins[0];
+        self.wins[1] += other.wins[1];
+        self.p0_wins += other.p0_wins;
+        self.game_draws += other.game_draws;
+        self.rounds += other.rounds;
+        self.first_player_wins += other.first_player_wins;
+        self.draws += other.draws;
+        self.turns += other.turns;
+        self.pairs += other.pairs;
+        self.pair_wins[0] += other.pair_wins[0];
+        self.pair_wins[1] += other.pair_wins[1];
+        for (reason, count) in other.reasons {
+            *self.reasons.entry(reason).or_default() += count;
+        }
+    }
+}
+
+/// Wilsonの信頼区間
+fn wilson(successes: u64, trials: u64) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let denominator = 1.0 + Z * Z / n;
+    let center = (p + Z * Z / (2.0 * n)) / denominator;
+    let half = Z * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt() / denominator;
+    ((center - half).max(0.0), (center + half).min(1.0))
+}
+
+fn rate(successes: u64, trials: u64) -> String {
+    let (low, high) = wilson(successes, trials);
+    format!(
+        "{:.1}% (95%信頼区間 {:.1}%〜{:.1}%)",
+        successes as f64 / trials.max(1) as f64 * 100.0,
+        low * 100.0,
+        high * 100.0
+    )
+}
+
+/// 1試合行い、勝者(引き分けは`None`)と席順の得点を返す。`seats`は席順に並べたボット
+fn play_game(
+    seats: &mut [Box<dyn Bot>; 2],
+    format: MatchFormat,
+    seed: u64,
+    rules: &RuleSet,
+) -> (Option<PlayerID>, [u32; 2], Stats) {
+    let mut game_manager = GameManager::with_format(format, seed, rules.clone());
+    let mut stats = Stats::default();
+    loop {
+        let first_player = game_manager.board().current_player();
+        let (winner, reason) = loop {
+            let current = game_manager.board().current_player();
+            let seat = &mut seats[current.denote() as usize];
+            let kekka = match seat.choose(&game_manager, current) {
+                Some(action) => game_manager
+                    .play_action(current, action)
+                    .expect("ボットは合法手を選ぶはず"),
+                None => game_manager.lose_round(current, RoundEndReason::Tumi),
+            };
+            stats.turns += 1;
+            match kekka {
+                Kekka::Continue => *game_manager.current_playerid_mut() = current.opposite(),
+                Kekka::REnd(winner, reason) => break (winner, reason),
+            }
+        };
+        stats.rounds += 1;
+        match winner {
+            Some(winner) if winner == first_player => stats.first_player_wins += 1,
+            Some(_) => {}
+            None => stats.draws += 1,
+        }
+        *stats.reasons.entry(reason).or_default() += 1;
+        game_manager.reset_round();
+        match game_manager.ended() {
+            None => game_manager.change_first_player(),
+            Some(winner) => {
+                let board = game_manager.board();
+                let scores = [board.score(PlayerID::Zero), board.score(PlayerID::One)];
+                return (winner, scores, stats);
+            }
+        }
+    }
+}
+
+pub fn simulate(args: &SimulateArgs) {
+    let master_seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let threads = args
+        .threads
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, |threads| threads.get());
+    let kinds = [args.bot0, args.bot1];
+    let rules = args.rules.clone().unwrap_or_default();
+    let format = args.format.format();
+    let next = AtomicU64::new(0);
+    let unit = if args.duplicate { 2 } else { 1 };
+    let start = Instant::now();
+    let stats = thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut stats = Stats::default();
+                    loop {
+                        // 同じ山札の2試合は同じスレッドで続けて行う
+                        let first = next.fetch_add(1, Ordering::Relaxed) * unit;
+                        if first >= args.games {
+                            return stats;
+                        }
+                        let mut scores = Vec::new();
+                        for game in first..first + unit {
+                            // 席の有利不利が偏らないよう、奇数番目の試合は席を入れ替える
+                            let swapped = game % 2 == 1;
+                            let seed = derive_seed(master_seed, game / unit);
+                            let mut seats = [0, 1].map(|seat| {
+                                let bot = if swapped { 1 - seat } else { seat };
+                                kinds[bot].create(
+                                    derive_seed(seed, u64::MAX - seat as u64),
+                                    args.think_time,
+                                )
+                            });
+                            let (winner, game_scores, mut game_stats) =
+                                play_game(&mut seats, format, seed, &rules);
+                            game_stats.games = 1;
+                            match winner {
+                                Some(winner) => {
+                                    let bot = winner.denote() as usize;
+                                    game_stats.wins[if swapped { 1 - bot } else { bot }] = 1;
+                                    if winner == PlayerID::Zero {
+                                        game_stats.p0_wins = 1;
+                                    }
+                                }
+                                None => game_stats.game_draws = 1,
+                            }
+                            stats.merge(game_stats);
+                            scores.push(game_scores);
+                        }
+                        if let [first, second] = scores[..] {
+                            stats.pairs += 1;
+                            if let (Some(winner), _) = duplicate_result(first, second) {
+                                stats.pair_wins[winner.denote() as usize] += 1;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut stats = Stats::default();
+        for worker in workers {
+            stats.merge(worker.join().expect("join失敗"));
+        }
+        stats
+    });
+    let names = kinds.map(|kind| kind.create(0, args.think_time).name().to_string());
+    println!(
+        "{} vs {}: {}試合 ({}, シード値: {}, {}スレッド, {:.1}秒)",
+        names[0],
+        names[1],
+        stats.games,
+        format,
+        master_seed,
+        threads,
+        start.elapsed().as_secs_f64()
+    );
+    println!("試合の勝率 (引き分け{}試合)", stats.game_draws);
+    for (bot, name) in names.iter().enumerate() {
+        println!(
+            "  bot{} {}: {}勝 {}",
+            bot,
+            name,
+            stats.wins[bot],
+            rate(stats.wins[bot], stats.games)
+        );
+    }
+    println!("  p0の席: {}", rate(stats.p0_wins, stats.games));
+    if stats.pairs > 0 {
+        println!(
+            "同じ山札の2試合の合計得点での勝率 (全{}組, 引き分け{}組)",
+            stats.pairs,
+            stats.pairs - stats.pair_wins[0] - stats.pair_wins[1]
+        );
+        for (bot, name) in names.iter().enumerate() {
+            println!(
+                "  bot{} {}: {}組 {}",
+                bot,
+                name,
+                stats.pair_wins[bot],
+                rate(stats.pair_wins[bot], stats.pairs)
+            );
+        }
+    }
+    println!(
+        "先手のラウンド勝率: {} (引き分け{}ラウンドを除く)",
+        rate(stats.first_player_wins, stats.rounds - stats.draws),
+        stats.draws
+    );
+    println!("ラウンドの終わり方");
+    for reason in [
+        RoundEndReason::Hit,
+        RoundEndReason::Tumi,
+        RoundEndReason::DeckOut,
+    ] {
+        let count = stats.reasons.get(&reason).copied().unwrap_or_default();
+        println!(
+            "  {:?}: {}ラウンド {:.1}%",
+            reason,
+            count,
+            count as f64 / stats.rounds as f64 * 100.0
+        );
+    }
+    println!(
+        "平均ラウンド長: {:.2}手 (全{}ラウンド)",
+        stats.turns as f64 / stats.rounds as f64,
+        stats.rounds
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wilson_without_trials_is_the_whole_range() {
+        assert_eq!(wilson(0, 0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn wilson_bounds_for_all_successes_and_all_failures() {
+        // p=1なら上限は1、下限はn/(n+z^2)。p=0はその裏返し
+        let (low, high) = wilson(10, 10);
+        assert_eq!(high, 1.0);
+        assert!((low - 10.0 / (10.0 + Z * Z)).abs() < 1e-12);
+        let (low, high) = wilson(0, 10);
+        assert_eq!(low, 0.0);
+        assert!((high - Z * Z / (10.0 + Z * Z)).abs() < 1e-12);
+    }
+}
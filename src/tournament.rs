@@ -0,0 +1,660 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs, io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Instant,
+};
+
+use engarde_server::{
+    errors::GameError,
+    game::derive_seed,
+    protocol::{ConnectionStart, Disconnected, PlayerID, ServerError},
+};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+    accept_backoff,
+    cli::{BotKind, TimeoutPenalty, TournamentArgs, TournamentFormat},
+    client_manager::{Client, Names},
+    duplicate_result, handshake, print, run_match,
+    transport::ChildProcess,
+    Entrant,
+};
+
+/// 参加者の一覧の1件。`Bot`も`Command`もなければTCPで接続してくるボット
+///
+/// ```json
+/// [{"Name": "greedy", "Bot": "greedy"}, {"Name": "mybot", "Command": "python3 bot.py"}, {"Name": "remote"}]
+/// ```
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct Entry {
+    name: String,
+    #[serde(default)]
+    bot: Option<BotKind>,
+    #[serde(default)]
+    command: Option<String>,
+}
+
+/// 順位表の1行
+#[derive(Default)]
+struct Standing {
+    /// 勝ちは2点、引き分けは1点。不戦勝は1組分の試合に勝ったものとして数える
+    points: u32,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+    /// ラウンドの得点から相手の得点を引いた合計
+    round_diff: i64,
+    /// 対戦した相手と、その相手から得た勝点
+    opponents: Vec<(usize, u32)>,
+    bye: bool,
+}
+
+/// 1組の対戦で席を入れ替えて行う試合数
+const GAMES_PER_PAIRING: u32 = 2;
+const WIN_POINTS: u32 = 2;
+const DRAW_POINTS: u32 = 1;
+
+struct Tournament {
+    args: TournamentArgs,
+    entries: Vec<Entry>,
+    standings: Vec<Standing>,
+    master_seed: u64,
+    match_id: u64,
+    /// TCPのボットの接続。参加者にTCPのボットがいるときだけ待ち受ける
+    lobby: Option<Receiver<TcpStream>>,
+    names: Names,
+    /// 名前を登録して自分の試合を待っているTCPのボットと、知らせた席
+    waiting: HashMap<String, (PlayerID, Client)>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_roster(args: &TournamentArgs) -> io::Result<Vec<Entry>> {
+    let json = fs::read_to_string(&args.roster)?;
+    let entries = serde_json::from_str::<Vec<Entry>>(&json)
+        .map_err(|e| invalid(format!("参加者の一覧を読めません: {}", e)))?;
+    if entries.len() < 2 {
+        return Err(invalid("参加者は2人以上にしてください".to_string()));
+    }
+    let mut names = HashSet::new();
+    for entry in &entries {
+        if !names.insert(entry.name.as_str()) {
+            return Err(invalid(format!("{}が重複しています", entry.name)));
+        }
+        if entry.bot.is_some() && entry.command.is_some() {
+            return Err(invalid(format!(
+                "{}: BotとCommandはどちらか1つにしてください",
+                entry.name
+            )));
+        }
+    }
+    Ok(entries)
+}
+
+/// 接続を受け付けて渡し続けるスレッドを立てる
+fn open_lobby(args: &TournamentArgs) -> io::Result<Receiver<TcpStream>> {
+    let listener = TcpListener::bind(SocketAddr::new(args.address, args.port))?;
+    print(format!("{}でTCPのボットを待ち受け中\n", listener.local_addr()?).as_str())?;
+    let timeout = args.match_args.timeout;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut failures = 0;
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    failures = 0;
+                    if let Err(e) = stream.set_write_timeout(timeout) {
+                        let _ = print(format!("接続受付エラー: {}\n", e).as_str());
+                        continue;
+                    }
+                    if sender.send(stream).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = print(format!("接続受付エラー: {}\n", e).as_str());
+                    thread::sleep(accept_backoff(failures));
+                    failures += 1;
+                }
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+impl Tournament {
+    /// 試合の席を用意する。ログ・棋譜・レーティングには参加者の一覧の名前を使う。
+    /// 用意できなければ理由を返し、その参加者はその試合の負け
+    fn prepare(&mut self, index: usize, id: PlayerID) -> Result<Entrant<ChildProcess>, String> {
+        let entry = &self.entries[index];
+        if let Some(kind) = entry.bot {
+            return Ok(Entrant::Bot(
+                kind,
+                self.args.think_time,
+                Some(entry.name.clone()),
+            ));
+        }
+        let args = &self.args.match_args;
+        if let Some(command) = &entry.command {
+            let child = ChildProcess::spawn(command).map_err(|e| e.to_string())?;
+            // 同じコマンドが何度出ても良いよう、名前の重複は確かめない
+            return handshake(
+                child,
+                id,
+                args.rules(),
+                args.format.format(),
+                args.timeout,
+                args.max_errors,
+                Names::default(),
+            )
+            .join()
+            .expect("join失敗")
+            .map(|mut client| {
+                // 子プロセスが名乗った名前でなく、参加者の一覧の名前で記録する
+                client.rename(&entry.name);
+                Entrant::Joined(client)
+            })
+            .map_err(|e| e.to_string());
+        }
+        let name = entry.name.clone();
+        let deadline = Instant::now() + self.args.join_timeout;
+        // 他の参加者が先に接続してきたら、その参加者の席まで待たせておく
+        while !self.waiting.contains_key(&name) {
+            let stream = self
+                .lobby
+                .as_ref()
+                .expect("TCPのボットがいれば待ち受けているはず")
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|_| "時間内に接続しませんでした".to_string())?;
+            let joined = handshake(
+                stream,
+                id,
+                args.rules(),
+                args.format.format(),
+                Some(deadline.saturating_duration_since(Instant::now())),
+                args.max_errors,
+                self.names.clone(),
+            )
+            .join()
+            .expect("join失敗");
+            match joined {
+                Ok(client) if self.entries.iter().any(|entry| entry.name == client.name()) => {
+                    self.waiting.insert(client.name().to_string(), (id, client));
+                }
+                Ok(mut client) => {
+                    let _ = client.send(&ServerError::new(GameError::NotScheduled));
+                }
+                Err(e) => {
+                    print(format!("接続エラー: {}\n", e).as_str()).map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        let (told, mut client) = self.waiting.remove(&name).expect("待たせているはず");
+        // 待たせている間に席が変わったら、改めて席を知らせる
+        if told != id {
+            client
+                .send(&ConnectionStart::new(
+                    id,
+                    args.rules(),
+                    args.format.format(),
+                ))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(Entrant::Joined(client))
+    }
+
+    /// 1試合行い、勝った参加者と席順の得点を返す。不参加がいるか、エラーで終わった試合なら得点は`None`
+    fn play_game(
+        &mut self,
+        seats: [usize; 2],
+        match_seed: u64,
+    ) -> io::Result<(Option<usize>, Option<[u32; 2]>)> {
+        let match_id = self.match_id;
+        self.match_id += 1;
+        let entrant0 = self.prepare(seats[0], PlayerID::Zero);
+        let entrant1 = self.prepare(seats[1], PlayerID::One);
+        let (entrant0, entrant1) = match (entrant0, entrant1) {
+            (Ok(entrant0), Ok(entrant1)) => (entrant0, entrant1),
+            (entrant0, entrant1) => {
+                let mut present = Vec::new();
+                for (seat, entrant) in [entrant0, entrant1].into_iter().enumerate() {
+                    match entrant {
+                        Ok(entrant) => present.push((seat, entrant)),
+                        Err(e) => print(
+                            format!(
+                                "試合{}: {}は不参加: {}\n",
+                                match_id, self.entries[seats[seat]].name, e
+                            )
+                            .as_str(),
+                        )?,
+                    }
+                }
+                // 来た方はその試合の勝ちにし、待っていたクライアントには相手が来なかったと知らせる
+                let mut winner = None;
+                for (seat, entrant) in present {
+                    winner = Some(seats[seat]);
+                    print(
+                        format!(
+                            "試合{}: {}の不戦勝\n",
+                            match_id, self.entries[seats[seat]].name
+                        )
+                        .as_str(),
+                    )?;
+                    if let Entrant::Joined(mut client) = entrant {
+                        let opponent = [PlayerID::One, PlayerID::Zero][seat];
+                        let _ = client.send(&Disconnected::new(opponent));
+                    }
+                }
+                return Ok((winner, None));
+            }
+        };
+        let result = match run_match(
+            entrant0,
+            entrant1,
+            &self.args.match_args,
+            [Names::default(), Names::default()],
+            match_seed,
+            match_id,
+        ) {
+            Ok(result) => result,
+            // 1試合の記録や出力に失敗しても大会は続け、その試合は無効にする
+            Err(e) => {
+                print(format!("試合{}: エラー終了: {}\n", match_id, e).as_str())?;
+                return Ok((None, None));
+            }
+        };
+        Ok((
+            result.winner.map(|winner| seats[winner.denote() as usize]),
+            Some(result.scores),
+        ))
+    }
+
+    /// `a`と`b`を席を入れ替えて対戦させ、結果を順位表に記録する
+    fn play_pairing(&mut self, a: usize, b: usize) -> io::Result<()> {
+        let mut wins = [0, 0];
+        let mut draws = 0;
+        // `a`・`b`それぞれの2試合の合計得点。不参加の試合は数えない
+        let mut totals = [0, 0];
+        let mut scores = Vec::new();
+        // 同じ山札で行うなら2試合とも1試合目の番号からシード値を作る
+        let duplicate_seed = derive_seed(self.master_seed, self.match_id);
+        for seats in [[a, b], [b, a]] {
+            let match_seed = if self.args.duplicate {
+                duplicate_seed
+            } else {
+                derive_seed(self.master_seed, self.match_id)
+            };
+            let (winner, game_scores) = self.play_game(seats, match_seed)?;
+            match game_scores {
+                Some([score0, score1]) if seats[0] == a => {
+                    totals[0] += score0;
+                    totals[1] += score1;
+                }
+                Some([score0, score1]) => {
+                    totals[0] += score1;
+                    totals[1] += score0;
+                }
+                None => {}
+            }
+            scores.push(game_scores);
+            match (winner, game_scores) {
+                (Some(winner), _) if winner == a => wins[0] += 1,
+                (Some(_), _) => wins[1] += 1,
+                (None, Some(_)) => draws += 1,
+                // 両者とも不参加か、エラーで終わった無効試合
+                (None, None) => {}
+            }
+        }
+        // 不参加の試合があれば合計得点では比べられないので、試合ごとの勝敗で勝点を数える
+        let paired = match scores[..] {
+            [Some(first), Some(second)] if self.args.duplicate => {
+                Some(duplicate_result(first, second).0)
+            }
+            _ => None,
+        };
+        let points = match paired {
+            Some(Some(PlayerID::Zero)) => [GAMES_PER_PAIRING * WIN_POINTS, 0],
+            Some(Some(PlayerID::One)) => [0, GAMES_PER_PAIRING * WIN_POINTS],
+            Some(None) => [GAMES_PER_PAIRING * DRAW_POINTS; 2],
+            None => wins.map(|wins| wins * WIN_POINTS + draws * DRAW_POINTS),
+        };
+        let diff = totals[0] as i64 - totals[1] as i64;
+        for (player, opponent, won, lost, points, diff) in [
+            (a, b, wins[0], wins[1], points[0], diff),
+            (b, a, wins[1], wins[0], points[1], -diff),
+        ] {
+            let standing = &mut self.standings[player];
+            standing.points += points;
+            standing.wins += won;
+            standing.losses += lost;
+            standing.draws += draws;
+            standing.round_diff += diff;
+            standing.opponents.push((opponent, points));
+        }
+        let mut summary = String::new();
+        if draws > 0 {
+            summary += &format!(" (引き分け{})", draws);
+        }
+        if paired.is_some() {
+            summary += &format!(" (合計{}-{}点)", totals[0], totals[1]);
+        }
+        print(
+            format!(
+                "{} {}-{} {}{}\n",
+                self.entries[a].name, wins[0], wins[1], self.entries[b].name, summary
+            )
+            .as_str(),
+        )
+    }
+
+    fn bye(&mut self, player: usize) -> io::Result<()> {
+        self.standings[player].points += GAMES_PER_PAIRING * WIN_POINTS;
+        self.standings[player].bye = true;
+        print(format!("{} 不戦勝\n", self.entries[player].name).as_str())
+    }
+
+    fn played(&self, a: usize, b: usize) -> bool {
+        self.standings[a]
+            .opponents
+            .iter()
+            .any(|&(opponent, _)| opponent == b)
+    }
+
+    /// スイス式の組み合わせ。勝点の順に上から、まだ当たっていない相手と組む
+    fn swiss_pairings(&self) -> (Vec<(usize, usize)>, Option<usize>) {
+        let mut order = (0..self.entries.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&player| Reverse(self.standings[player].points));
+        // 奇数なら、まだ不戦勝になっていない一番下の参加者を休ませる
+        let bye = (order.len() % 2 == 1).then(|| {
+            let index = order
+                .iter()
+                .rposition(|&player| !self.standings[player].bye)
+                .unwrap_or(order.len() - 1);
+            order.remove(index)
+        });
+        let mut pairings = Vec::new();
+        if !self.pair_fresh(&mut order.clone(), &mut pairings) {
+            // 再戦を避けられなければ上から順に組む
+            pairings = order.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        }
+        (pairings, bye)
+    }
+
+    /// 上の参加者から順に、全員をまだ当たっていない相手と組む。組めなければ`false`
+    fn pair_fresh(&self, rest: &mut Vec<usize>, pairings: &mut Vec<(usize, usize)>) -> bool {
+        if rest.is_empty() {
+            return true;
+        }
+        let a = rest.remove(0);
+        for index in 0..rest.len() {
+            let b = rest[index];
+            if self.played(a, b) {
+                continue;
+            }
+            rest.remove(index);
+            pairings.push((a, b));
+            if self.pair_fresh(rest, pairings) {
+                return true;
+            }
+            pairings.pop();
+            rest.insert(index, b);
+        }
+        rest.insert(0, a);
+        false
+    }
+
+    /// ブッフホルツ(対戦相手の勝点の合計)
+    fn buchholz(&self, player: usize) -> u32 {
+        self.standings[player]
+            .opponents
+            .iter()
+            .map(|&(opponent, _)| self.standings[opponent].points)
+            .sum()
+    }
+
+    /// ソネボーン・バーガー(相手から得た勝点に相手の勝点を掛けた合計)
+    fn sonneborn_berger(&self, player: usize) -> u32 {
+        self.standings[player]
+            .opponents
+            .iter()
+            .map(|&(opponent, points)| points * self.standings[opponent].points)
+            .sum()
+    }
+
+    /// 順位の順に、参加者・勝点・ブッフホルツ・ソネボーン・バーガー・得失点を並べる
+    fn ranking(&self) -> Vec<(usize, u32, u32, u32, i64)> {
+        let mut rows = (0..self.entries.len())
+            .map(|player| {
+                let standing = &self.standings[player];
+                (
+                    player,
+                    standing.points,
+                    self.buchholz(player),
+                    self.sonneborn_berger(player),
+                    standing.round_diff,
+                )
+            })
+            .collect::<Vec<_>>();
+        // 勝点、ブッフホルツ、ソネボーン・バーガー、得失点の順に比べ、それでも並べば一覧の順
+        rows.sort_by_key(|&(player, points, buchholz, sb, diff)| {
+            (Reverse((points, buchholz, sb, diff)), player)
+        });
+        rows
+    }
+
+    fn print_standings(&self) -> io::Result<()> {
+        let width = self
+            .entries
+            .iter()
+            .map(|entry| entry.name.chars().count())
+            .max()
+            .unwrap_or_default();
+        let mut table = format!(
+            "順位 {:<width$} 勝点 勝 負 分 ブッフホルツ SB 得失点\n",
+            "名前"
+        );
+        for (rank, (player, points, buchholz, sb, diff)) in self.ranking().into_iter().enumerate() {
+            let standing = &self.standings[player];
+            table += &format!(
+                "{:>4} {:<width$} {:>4} {:>2} {:>2} {:>2} {:>12} {:>2} {:>+6}\n",
+                rank + 1,
+                self.entries[player].name,
+                points,
+                standing.wins,
+                standing.losses,
+                standing.draws,
+                buchholz,
+                sb,
+                diff
+            );
+        }
+        print(table.as_str())
+    }
+}
+
+pub fn tournament(mut args: TournamentArgs) -> io::Result<()> {
+    let entries = read_roster(&args)?;
+    if entries.iter().any(|entry| entry.command.is_some()) {
+        // 時間を超えたボットは強制終了するので、その試合は続けられない
+        args.match_args.on_timeout = TimeoutPenalty::Game;
+    }
+    let lobby = entries
+        .iter()
+        .any(|entry| entry.bot.is_none() && entry.command.is_none())
+        .then(|| open_lobby(&args))
+        .transpose()?;
+    let master_seed = args
+        .match_args
+        .seed
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    print(
+        format!(
+            "{}人で大会を始めます (シード値: {})\n",
+            entries.len(),
+            master_seed
+        )
+        .as_str(),
+    )?;
+    let mut tournament = Tournament {
+        standings: entries.iter().map(|_| Standing::default()).collect(),
+        entries,
+        args,
+        master_seed,
+        match_id: 0,
+        lobby,
+        names: Names::default(),
+        waiting: HashMap::new(),
+    };
+    let players = tournament.entries.len();
+    match tournament.args.format {
+        TournamentFormat::RoundRobin => {
+            for a in 0..players {
+                for b in a + 1..players {
+                    tournament.play_pairing(a, b)?;
+                }
+            }
+        }
+        TournamentFormat::Swiss => {
+            let rounds = tournament
+                .args
+                .rounds
+                .unwrap_or_else(|| (players as u32).next_power_of_two().trailing_zeros().max(1));
+            for round in 1..=rounds {
+                print(format!("第{}回戦\n", round).as_str())?;
+                let (pairings, bye) = tournament.swiss_pairings();
+                for (a, b) in pairings {
+                    tournament.play_pairing(a, b)?;
+                }
+                if let Some(player) = bye {
+                    tournament.bye(player)?;
+                }
+            }
+        }
+    }
+    tournament.print_standings()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[command(flatten)]
+        args: TournamentArgs,
+    }
+
+    /// 組み込みのボットだけが参加し、勝点を決めた大会
+    fn tournament(points: &[u32]) -> Tournament {
+        Tournament {
+            args: TestArgs::parse_from(["engarde_server", "roster.json", "-r", "1"]).args,
+            entries: (0..points.len())
+                .map(|player| Entry {
+                    name: format!("bot{}", player),
+                    bot: Some(BotKind::Random),
+                    command: None,
+                })
+                .collect(),
+            standings: points
+                .iter()
+                .map(|&points| Standing {
+                    points,
+                    ..Standing::default()
+                })
+                .collect(),
+            master_seed: 0,
+            match_id: 0,
+            lobby: None,
+            names: Names::default(),
+            waiting: HashMap::new(),
+        }
+    }
+
+    /// `a`と`b`が対戦し、それぞれ相手から勝点を得たことにする。勝点の合計は変えない
+    fn meet(tournament: &mut Tournament, a: usize, b: usize, points: [u32; 2]) {
+        tournament.standings[a].opponents.push((b, points[0]));
+        tournament.standings[b].opponents.push((a, points[1]));
+    }
+
+    #[test]
+    fn swiss_avoids_rematches() {
+        let mut tournament = tournament(&[4, 4, 0, 0]);
+        meet(&mut tournament, 0, 1, [2, 2]);
+        assert_eq!(tournament.swiss_pairings(), (vec![(0, 2), (1, 3)], None));
+    }
+
+    #[test]
+    fn swiss_rematches_only_when_unavoidable() {
+        let mut tournament = tournament(&[4, 0]);
+        meet(&mut tournament, 0, 1, [4, 0]);
+        assert_eq!(tournament.swiss_pairings(), (vec![(0, 1)], None));
+    }
+
+    #[test]
+    fn swiss_gives_the_bye_to_the_lowest_score() {
+        let tournament = tournament(&[0, 4, 2]);
+        assert_eq!(tournament.swiss_pairings(), (vec![(1, 2)], Some(0)));
+    }
+
+    #[test]
+    fn swiss_gives_no_player_a_second_bye() {
+        let mut tournament = tournament(&[4, 2, 0]);
+        tournament.standings[2].bye = true;
+        assert_eq!(tournament.swiss_pairings(), (vec![(0, 2)], Some(1)));
+    }
+
+    #[test]
+    fn swiss_pairs_everyone_but_the_bye_once_with_an_odd_count() {
+        let tournament = tournament(&[0; 5]);
+        let (pairings, bye) = tournament.swiss_pairings();
+        let mut players = pairings
+            .into_iter()
+            .flat_map(|(a, b)| [a, b])
+            .chain(bye)
+            .collect::<Vec<_>>();
+        players.sort();
+        assert_eq!(players, [0, 1, 2, 3, 4]);
+        assert_eq!(bye, Some(4));
+    }
+
+    #[test]
+    fn tiebreaks_rank_by_buchholz_then_sonneborn_berger_then_round_diff() {
+        let mut tournament = tournament(&[4, 4, 4, 4, 6, 2]);
+        // 0と1はブッフホルツが並び、相手から得た勝点の重みで0が上
+        meet(&mut tournament, 0, 4, [4, 0]);
+        meet(&mut tournament, 0, 5, [0, 4]);
+        meet(&mut tournament, 1, 4, [0, 4]);
+        meet(&mut tournament, 1, 5, [4, 0]);
+        // 2と3は相手も勝点も同じで、得失点で3が上
+        meet(&mut tournament, 2, 5, [4, 0]);
+        meet(&mut tournament, 3, 5, [4, 0]);
+        tournament.standings[3].round_diff = 5;
+        assert_eq!(tournament.buchholz(0), 8);
+        assert_eq!(tournament.sonneborn_berger(0), 24);
+        assert_eq!(tournament.sonneborn_berger(1), 8);
+        let order = tournament
+            .ranking()
+            .into_iter()
+            .map(|(player, ..)| player)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [4, 0, 1, 3, 2, 5]);
+    }
+
+    #[test]
+    fn player_whose_opponent_does_not_come_wins_the_game() {
+        let mut tournament = tournament(&[0, 0]);
+        tournament.entries[0].bot = None;
+        tournament.entries[0].command = Some("exit 0".to_string());
+        assert_eq!(tournament.play_game([0, 1], 0).unwrap(), (Some(1), None));
+    }
+}
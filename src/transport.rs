@@ -0,0 +1,185 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// クライアントとの通信路。読み込みと書き込みを別々のスレッドで使えるように分ける
+///
+/// ソケットのほか、`std::io::pipe`や子プロセスの標準入出力のような
+/// `(読み込み側, 書き込み側)`の組もそのまま使える
+pub trait Transport: Send + 'static {
+    type Reader: Read + Send + 'static;
+    type Writer: Write + Send + 'static;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)>;
+}
+
+impl<R, W> Transport for (R, W)
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    type Reader = R;
+    type Writer = W;
+
+    fn split(self) -> io::Result<(R, W)> {
+        Ok(self)
+    }
+}
+
+/// 複製して読み書きに使い、両方向をまとめて閉じられるソケット
+pub trait Socket: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// ソケットの書き込み側。落とすとソケットを閉じ、読み込みスレッドも止まる
+pub struct SocketWriter<S: Socket>(S);
+
+impl<S: Socket> Write for SocketWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<S: Socket> Drop for SocketWriter<S> {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown();
+    }
+}
+
+impl<S: Socket> Transport for S {
+    type Reader = S;
+    type Writer = SocketWriter<S>;
+
+    fn split(self) -> io::Result<(S, SocketWriter<S>)> {
+        Ok((self.try_clone()?, SocketWriter(self)))
+    }
+}
+
+/// クライアントの接続を待ち受ける
+pub trait Listener {
+    type Stream: Socket;
+
+    fn accept_stream(&self) -> io::Result<Self::Stream>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept_stream(&self) -> io::Result<TcpStream> {
+        self.accept().map(|(stream, _)| stream)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept_stream(&self) -> io::Result<UnixStream> {
+        self.accept().map(|(stream, _)| stream)
+    }
+}
+
+/// 子プロセスとして動かすボット。標準入出力でJSONの行をやり取りする
+pub struct ChildProcess(Child);
+
+impl ChildProcess {
+    /// `command`をシェル経由で起動する。標準エラー出力はサーバーのものを引き継ぐ
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        // execでシェルを置き換え、強制終了がボット本体に届くようにする
+        #[cfg(unix)]
+        let mut shell = Command::new("sh");
+        #[cfg(unix)]
+        shell.arg("-c").arg(format!("exec {}", command));
+        #[cfg(windows)]
+        let mut shell = Command::new("cmd");
+        #[cfg(windows)]
+        shell.arg("/C").arg(command);
+        let child = shell
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        Ok(Self(child))
+    }
+}
+
+/// 子プロセスの標準入力。落とすとプロセスを強制終了する
+pub struct ChildWriter {
+    stdin: ChildStdin,
+    child: Child,
+}
+
+impl Write for ChildWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Drop for ChildWriter {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Transport for ChildProcess {
+    type Reader = ChildStdout;
+    type Writer = ChildWriter;
+
+    fn split(mut self) -> io::Result<(ChildStdout, ChildWriter)> {
+        let stdout = self.0.stdout.take().expect("標準出力をパイプにしたはず");
+        let stdin = self.0.stdin.take().expect("標準入力をパイプにしたはず");
+        Ok((
+            stdout,
+            ChildWriter {
+                stdin,
+                child: self.0,
+            },
+        ))
+    }
+}